@@ -1,4 +1,4 @@
-use intcode::Intcode;
+use intcode::{Intcode, Polynomial};
 use std::fs;
 
 const TARGET: isize = 19690720;
@@ -17,55 +17,117 @@ fn main() {
 }
 
 fn intcode_with_custom_inputs(s: &str, noun: isize, verb: isize) -> Intcode {
-    let mut ic = Intcode::new(s);
+    let mut ic = Intcode::new(s).unwrap();
     ic.mutate_memory(1, noun);
     ic.mutate_memory(2, verb);
     ic
 }
 
+#[allow(clippy::zero_prefixed_literal)]
 fn part_1(s: &str) -> isize {
     // New intcode instance with given input and code 1202
     let mut tape = intcode_with_custom_inputs(s, 12, 02);
 
     // Execute the program
-    tape.execute();
+    tape.execute().unwrap();
 
     // Return value in cell 0
     tape.read(0)
 }
 
 fn part_2(s: &str) -> isize {
-    // Search Algorithm: add a layer to the onion each time.
-    // 0 1 4
-    // 3 2 5
-    // 8 7 6
-
-    let mut active_tape: Intcode;
-    let mut layer = 0;
-
-    // Search loop through each layer
-    loop {
-        // Search the top
-        for noun in 0..layer {
-            active_tape = intcode_with_custom_inputs(&s, noun, layer);
-            active_tape.execute();
-            if active_tape.read(0) == TARGET {
-                return 100 * noun + layer;
-            }
-        }
+    match linear_formula(s) {
+        Some(formula) => solve_linear(
+            formula.coefficient("noun") as i128,
+            formula.coefficient("verb") as i128,
+            TARGET as i128 - formula.constant_term() as i128,
+        )
+        .map(|(noun, verb)| 100 * noun + verb),
+        None => search(s),
+    }
+    .expect("some noun and verb hit the target")
+}
+
+/// Run the program with the noun and verb left as symbols, so cell 0 comes out
+/// as a formula in them rather than a number. Returns `None` unless that
+/// formula is linear.
+fn linear_formula(s: &str) -> Option<Polynomial> {
+    let mut tape = Intcode::new(s).ok()?.symbolic();
+    tape.set_symbol(1, "noun");
+    tape.set_symbol(2, "verb");
+    tape.execute().ok()?;
+    let formula = tape.read(0).to_polynomial().ok()?;
+    if formula.degree() <= 1 {
+        Some(formula)
+    } else {
+        None
+    }
+}
 
-        // Search the right
-        // TODO I think I'm double-checking the diagonal
-        for verb in 0..layer {
-            active_tape = intcode_with_custom_inputs(&s, layer, verb);
-            active_tape.execute();
-            if active_tape.read(0) == TARGET {
-                return 100 * layer + verb;
+/// The noun and verb in 0..100 with `a * noun + b * verb == rest`, taking the
+/// smallest noun if there is more than one
+fn solve_linear(a: i128, b: i128, rest: i128) -> Option<(isize, isize)> {
+    let in_range = |value: i128| (0..100).contains(&value);
+    let (noun, verb) = match (a, b) {
+        (0, 0) if rest == 0 => (0, 0),
+        (0, 0) => return None,
+        (a, 0) if rest % a == 0 => (rest / a, 0),
+        (0, b) if rest % b == 0 => (0, rest / b),
+        (_, 0) | (0, _) => return None,
+        (a, b) => {
+            // The nouns that work are those with a * noun = rest (mod b), which
+            // repeat every |b| / gcd(a, b)
+            let (gcd, inverse) = extended_gcd(a, b.abs());
+            if rest % gcd != 0 {
+                return None;
             }
+            let step = b.abs() / gcd;
+            let first = (rest / gcd).rem_euclid(step) * inverse.rem_euclid(step) % step;
+            (first..100)
+                .step_by(step.min(100) as usize)
+                .map(|noun| (noun, (rest - a * noun) / b))
+                .find(|&(_, verb)| in_range(verb))?
         }
+    };
+    if in_range(noun) && in_range(verb) {
+        Some((noun as isize, verb as isize))
+    } else {
+        None
+    }
+}
 
-        layer += 1;
+/// The greatest common divisor `g` of `a` and `b`, along with an `x` such that
+/// `a * x = g (mod b)`
+fn extended_gcd(a: i128, b: i128) -> (i128, i128) {
+    let (mut old_r, mut r) = (a, b);
+    let (mut old_x, mut x) = (1, 0);
+    while r != 0 {
+        let quotient = old_r / r;
+        old_r -= quotient * r;
+        std::mem::swap(&mut old_r, &mut r);
+        old_x -= quotient * x;
+        std::mem::swap(&mut old_x, &mut x);
     }
+    if old_r < 0 {
+        (-old_r, -old_x)
+    } else {
+        (old_r, old_x)
+    }
+}
+
+/// Try every noun and verb on the concrete machine, for programs whose formula
+/// can't be solved directly
+fn search(s: &str) -> Option<isize> {
+    let template = Intcode::new(s).unwrap();
+    (0..100)
+        .flat_map(|noun| (0..100).map(move |verb| (noun, verb)))
+        .find(|&(noun, verb)| {
+            let mut tape = template.clone();
+            tape.mutate_memory(1, noun);
+            tape.mutate_memory(2, verb);
+            tape.execute().is_ok() && tape.read(0) == TARGET
+        })
+        .map(|(noun, verb)| 100 * noun + verb)
 }
 
 #[cfg(test)]
@@ -74,46 +136,80 @@ mod tests {
 
     #[test]
     fn long_example() {
-        let mut experimental = Intcode::new("1,9,10,3,2,3,11,0,99,30,40,50");
+        let mut experimental = Intcode::new("1,9,10,3,2,3,11,0,99,30,40,50").unwrap();
         let expected = "3500,9,10,70,2,3,11,0,99,30,40,50";
 
-        experimental.execute();
+        experimental.execute().unwrap();
         assert_eq!(expected, experimental.memory_string());
     }
 
     #[test]
     fn ex1_1() {
-        let mut experimental = Intcode::new("1,0,0,0,99");
+        let mut experimental = Intcode::new("1,0,0,0,99").unwrap();
         let expected = "2,0,0,0,99";
 
-        experimental.execute();
+        experimental.execute().unwrap();
         assert_eq!(expected, experimental.memory_string());
     }
 
     #[test]
     fn ex1_2() {
-        let mut experimental = Intcode::new("2,3,0,3,99");
+        let mut experimental = Intcode::new("2,3,0,3,99").unwrap();
         let expected = "2,3,0,6,99";
 
-        experimental.execute();
+        experimental.execute().unwrap();
         assert_eq!(expected, experimental.memory_string());
     }
 
     #[test]
     fn ex1_3() {
-        let mut experimental = Intcode::new("2,4,4,5,99,0");
+        let mut experimental = Intcode::new("2,4,4,5,99,0").unwrap();
         let expected = "2,4,4,5,99,9801";
 
-        experimental.execute();
+        experimental.execute().unwrap();
         assert_eq!(expected, experimental.memory_string());
     }
 
     #[test]
     fn ex1_4() {
-        let mut experimental = Intcode::new("1,1,1,4,99,5,6,0,99");
+        let mut experimental = Intcode::new("1,1,1,4,99,5,6,0,99").unwrap();
         let expected = "30,1,1,4,2,5,6,0,99";
 
-        experimental.execute();
+        experimental.execute().unwrap();
         assert_eq!(expected, experimental.memory_string());
     }
+
+    #[test]
+    fn part_2_hits_target() {
+        let s = include_str!("../input.txt");
+        let answer = part_2(s);
+        let mut tape = intcode_with_custom_inputs(s, answer / 100, answer % 100);
+        tape.execute().unwrap();
+        assert_eq!(tape.read(0), TARGET);
+    }
+
+    #[test]
+    fn solve_linear_zero_coefficients() {
+        assert_eq!(solve_linear(0, 0, 0), Some((0, 0)));
+        assert_eq!(solve_linear(0, 0, 1), None);
+        assert_eq!(solve_linear(5, 0, 35), Some((7, 0)));
+        assert_eq!(solve_linear(0, 5, 35), Some((0, 7)));
+        assert_eq!(solve_linear(0, 5, 36), None);
+    }
+
+    #[test]
+    fn solve_linear_both_coefficients() {
+        assert_eq!(solve_linear(3, 7, 3 * 12 + 7 * 34), Some((5, 37)));
+        assert_eq!(solve_linear(-2, 1, -10), Some((5, 0)));
+        assert_eq!(solve_linear(100, 1, 1202), Some((12, 2)));
+        assert_eq!(solve_linear(2, 4, 7), None);
+    }
+
+    #[test]
+    fn part_2_nonlinear_falls_back_to_search() {
+        // Cell 0 ends up as noun * verb + 19690312, which hits the target when
+        // noun * verb is 408
+        let s = "1102,0,0,0,1001,0,19690312,0,99";
+        assert_eq!(part_2(s), 668);
+    }
 }
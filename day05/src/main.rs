@@ -9,13 +9,13 @@ fn main() {
     // Compute Results
     let mut input1 = VecDeque::new();
     input1.push_back(1);
-    let mut machine1 = Intcode::new_with_input(&s, input1);
-    machine1.execute();
+    let mut machine1 = Intcode::new_with_input(&s, &input1).unwrap();
+    machine1.execute().unwrap();
 
     let mut input2 = VecDeque::new();
     input2.push_back(5);
-    let mut machine2 = Intcode::new_with_input(&s, input2);
-    machine2.execute();
+    let mut machine2 = Intcode::new_with_input(&s, &input2).unwrap();
+    machine2.execute().unwrap();
 
     // Print results
     println!("TEST output: {:?}", machine1.get_output());
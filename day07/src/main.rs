@@ -1,54 +1,81 @@
-use intcode::Intcode;
-use std::collections::{HashSet, VecDeque};
+use intcode::{Intcode, Topology};
+use std::collections::HashSet;
 use std::fs;
 
 fn main() {
     let s = fs::read_to_string("input.txt").unwrap();
 
-    // Find all possible orderings
-    let remaining: HashSet<u8> = vec![0, 1, 2, 3, 4].iter().map(|x| *x as u8).collect();
-    let mut orderings = Vec::new();
-    get_all_orderings(&Vec::new(), &remaining, &mut orderings);
-    // println!("{:?}", orderings);
-
-    // Try all orderings
-    let max_thrust = orderings.iter().map(|o| amp_chain(&s, o)).max().unwrap();
-
-    println!("Part 1 result: {}", max_thrust);
+    println!("Part 1 result: {}", part1(&s));
+    println!("Part 2 result: {}", part2(&s));
 }
 
-fn get_all_orderings(used: &Vec<u8>, remaining: &HashSet<u8>, mut results: &mut Vec<Vec<u8>>) {
-    if remaining.len() == 0 {
-        results.push(used.clone());
+fn get_all_orderings(used: &[u8], remaining: &HashSet<u8>, results: &mut Vec<Vec<u8>>) {
+    if remaining.is_empty() {
+        results.push(used.to_vec());
     }
 
     for item in remaining {
-        let mut new_used = used.clone();
+        let mut new_used = used.to_vec();
         new_used.push(*item);
         let mut new_remaining = remaining.clone();
         new_remaining.remove(item);
-        get_all_orderings(&new_used, &new_remaining, &mut results);
+        get_all_orderings(&new_used, &new_remaining, results);
     }
 }
 
+/// Find all possible orderings of the given phase settings
+fn orderings_of(phases: &[u8]) -> Vec<Vec<u8>> {
+    let remaining: HashSet<u8> = phases.iter().copied().collect();
+    let mut orderings = Vec::new();
+    get_all_orderings(&Vec::new(), &remaining, &mut orderings);
+    orderings
+}
+
 fn part1(s: &str) -> isize {
-    //TODO
-    1
+    // Try all orderings
+    orderings_of(&[0, 1, 2, 3, 4])
+        .iter()
+        .map(|o| amp_chain(s, o))
+        .max()
+        .unwrap()
 }
 
-fn amp_chain(s: &str, phases: &Vec<u8>) -> isize {
-    let mut buffer: VecDeque<isize> = VecDeque::new();
-    buffer.push_back(0);
+fn part2(s: &str) -> isize {
+    // Try all orderings
+    orderings_of(&[5, 6, 7, 8, 9])
+        .iter()
+        .map(|o| feedback_loop(s, o))
+        .max()
+        .unwrap()
+}
 
-    for i in 0..5 {
-        buffer.push_front(phases[i] as isize);
+/// Build one amplifier per phase setting, each with its phase setting as its first input
+fn amplifiers(s: &str, phases: &[u8]) -> Vec<Intcode> {
+    phases
+        .iter()
+        .map(|phase| {
+            let mut amp = Intcode::new(s).unwrap();
+            amp.push_input(*phase as isize);
+            amp
+        })
+        .collect()
+}
 
-        let mut amp = Intcode::new_with_input(&s, &buffer);
-        amp.execute();
+fn amp_chain(s: &str, phases: &[u8]) -> isize {
+    let mut chain = Topology::chain(amplifiers(s, phases));
+    chain.machine_mut(0).push_input(0);
+    chain.run().unwrap();
 
-        buffer = amp.get_output();
-    }
-    buffer[0]
+    chain.last_output(phases.len() - 1).unwrap()
+}
+
+fn feedback_loop(s: &str, phases: &[u8]) -> isize {
+    // Pass the signal around the loop until the last amplifier halts
+    let mut ring = Topology::ring(amplifiers(s, phases));
+    ring.machine_mut(0).push_input(0);
+    ring.run().unwrap();
+
+    ring.last_output(phases.len() - 1).unwrap()
 }
 
 #[cfg(test)]
@@ -58,18 +85,34 @@ mod tests {
     #[test]
     fn ex1_1() {
         let program = "3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0";
-        assert_eq!(amp_chain(program, &vec![4, 3, 2, 1, 0]), 43210);
+        assert_eq!(amp_chain(program, &[4, 3, 2, 1, 0]), 43210);
+        assert_eq!(part1(program), 43210);
     }
 
     #[test]
     fn ex1_2() {
         let program = "3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0";
-        assert_eq!(amp_chain(program, &vec![0, 1, 2, 3, 4]), 54321);
+        assert_eq!(amp_chain(program, &[0, 1, 2, 3, 4]), 54321);
     }
 
     #[test]
     fn ex1_3() {
         let program = "3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0";
-        assert_eq!(amp_chain(program, &vec![1, 0, 4, 3, 2]), 65210);
+        assert_eq!(amp_chain(program, &[1, 0, 4, 3, 2]), 65210);
+    }
+
+    #[test]
+    fn ex2_1() {
+        let program =
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+        assert_eq!(feedback_loop(program, &[9, 8, 7, 6, 5]), 139629729);
+        assert_eq!(part2(program), 139629729);
+    }
+
+    #[test]
+    fn ex2_2() {
+        let program = "3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,-5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10";
+        assert_eq!(feedback_loop(program, &[9, 7, 8, 5, 6]), 18216);
+        assert_eq!(part2(program), 18216);
     }
 }
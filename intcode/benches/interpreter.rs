@@ -0,0 +1,142 @@
+//! Compares execution speed with and without the decoded instruction cache,
+//! and against the interpreter from before this series of changes, which is
+//! kept verbatim in `baseline`.
+//! Run with `cargo bench -p intcode`.
+//!
+//! The cache only pays off for code that runs more than once. Each run of the
+//! day 2 search executes every instruction once, so the cache makes no
+//! difference there, and the warm-up only keeps it from costing anything.
+
+mod baseline;
+
+use intcode::{assemble, Intcode};
+use std::time::{Duration, Instant};
+
+const DAY02: &str = include_str!("../../day02/input.txt");
+const TARGET: isize = 19_690_720;
+
+/// Counts down from its input, so almost all the time is spent in a three
+/// instruction loop
+const COUNTDOWN: &str = "
+    IN c
+loop:
+    ADD c, #-1, c
+    JNZ c, #loop
+    OUT c
+    HLT
+c:  data 0
+";
+
+/// Run the given closure a few times and report the fastest
+fn time(runs: usize, mut f: impl FnMut()) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+/// The day 2 part 2 brute force search as day 2 used to run it on the baseline
+/// interpreter, parsing a fresh machine for every noun and verb
+fn day02_search_baseline() {
+    let mut found = None;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            let mut machine = baseline::Intcode::new(DAY02);
+            machine.mutate_memory(1, noun);
+            machine.mutate_memory(2, verb);
+            machine.execute();
+            if machine.read(0) == TARGET {
+                found = Some(100 * noun + verb);
+            }
+        }
+    }
+    assert!(found.is_some());
+}
+
+/// The same search, parsing a fresh machine for every noun and verb
+fn day02_search_parsing(cached: bool) {
+    let mut found = None;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            let mut machine = Intcode::new(DAY02).unwrap();
+            machine.set_decode_cache(cached);
+            machine.mutate_memory(1, noun);
+            machine.mutate_memory(2, verb);
+            machine.execute().unwrap();
+            if machine.read(0) == TARGET {
+                found = Some(100 * noun + verb);
+            }
+        }
+    }
+    assert!(found.is_some());
+}
+
+/// The same search, cloning a machine parsed once. The baseline interpreter
+/// can't be cloned.
+fn day02_search_cloning(cached: bool) {
+    let mut template = Intcode::new(DAY02).unwrap();
+    template.set_decode_cache(cached);
+    let mut found = None;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            let mut machine = template.clone();
+            machine.mutate_memory(1, noun);
+            machine.mutate_memory(2, verb);
+            machine.execute().unwrap();
+            if machine.read(0) == TARGET {
+                found = Some(100 * noun + verb);
+            }
+        }
+    }
+    assert!(found.is_some());
+}
+
+/// One long run around a tight loop on the baseline interpreter
+fn countdown_baseline(program: &str) {
+    let input = vec![3_000_000].into_iter().collect();
+    let mut machine = baseline::Intcode::new_with_input(program, &input);
+    machine.execute();
+}
+
+/// One long run around a tight loop
+fn countdown(program: &str, cached: bool) {
+    let mut machine = Intcode::new(program).unwrap();
+    machine.set_decode_cache(cached);
+    machine.push_input(3_000_000);
+    machine.execute().unwrap();
+}
+
+fn main() {
+    let program = assemble(COUNTDOWN).unwrap();
+
+    println!(
+        "day02 search, parsing (baseline): {:?}",
+        time(5, day02_search_baseline)
+    );
+    println!(
+        "countdown (baseline): {:?}",
+        time(5, || countdown_baseline(&program))
+    );
+    for &cached in &[false, true] {
+        let label = if cached { "cached" } else { "uncached" };
+        println!(
+            "day02 search, parsing ({}): {:?}",
+            label,
+            time(5, || day02_search_parsing(cached))
+        );
+        println!(
+            "day02 search, cloning ({}): {:?}",
+            label,
+            time(5, || day02_search_cloning(cached))
+        );
+        println!(
+            "countdown ({}): {:?}",
+            label,
+            time(5, || countdown(&program, cached))
+        );
+    }
+}
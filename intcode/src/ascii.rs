@@ -0,0 +1,153 @@
+use crate::{InputSource, Intcode, IntcodeError, OutputSink, RunState};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+
+/// Whether a value is in the ASCII range and so should be treated as text
+fn is_ascii(value: isize) -> bool {
+    (0..128).contains(&value)
+}
+
+impl Intcode {
+    /// Push a line of text onto the input queue as ASCII codes, followed by a newline
+    pub fn push_line(&mut self, line: &str) {
+        for c in line.bytes() {
+            self.push_input(c as isize);
+        }
+        self.push_input('\n' as isize);
+    }
+
+    /// Run until the text output so far ends with the given prompt, or until the machine
+    /// halts or needs input. Returns the text, including the prompt.
+    /// Any non-ASCII values output along the way are left on the output tape.
+    pub fn read_until_prompt(&mut self, prompt: &str) -> Result<String, IntcodeError> {
+        let mut text = String::new();
+
+        while !text.ends_with(prompt) {
+            match self.run_until_output()? {
+                RunState::Output(value) if is_ascii(value) => text.push(value as u8 as char),
+                RunState::Output(value) => self.output.push_back(value),
+                RunState::Halted | RunState::NeedsInput => break,
+            }
+        }
+
+        Ok(text)
+    }
+
+    /// Take everything from the output tape, as text. Values outside the ASCII range,
+    /// such as a puzzle answer after a rendered map, are returned separately in order.
+    pub fn take_ascii_output(&mut self) -> (String, Vec<isize>) {
+        let mut text = String::new();
+        let mut values = Vec::new();
+
+        for value in self.take_output() {
+            if is_ascii(value) {
+                text.push(value as u8 as char);
+            } else {
+                values.push(value);
+            }
+        }
+
+        (text, values)
+    }
+
+    /// Run the machine attached to the terminal. Text output is printed as it comes,
+    /// and each line typed is sent as input when the machine asks for it. Values
+    /// outside the ASCII range are printed as numbers on their own line.
+    /// Stops when the machine halts, or needs input after stdin is closed.
+    pub fn run_interactive(&mut self) -> Result<RunState, IntcodeError> {
+        self.run_with(AsciiStdin::default(), AsciiStdout)
+    }
+}
+
+/// Input read from stdin a line at a time, and sent as ASCII codes
+#[derive(Default)]
+pub struct AsciiStdin {
+    pending: VecDeque<isize>,
+}
+
+impl InputSource for AsciiStdin {
+    fn next_input(&mut self) -> Option<isize> {
+        if self.pending.is_empty() {
+            // Make sure any prompt is visible before waiting on the user
+            io::stdout().flush().ok()?;
+
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            self.pending.extend(line.bytes().map(|c| c as isize));
+            self.pending.push_back('\n' as isize);
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+/// Output printed to stdout as text, with values outside the ASCII range printed as
+/// numbers on their own line
+pub struct AsciiStdout;
+
+impl OutputSink for AsciiStdout {
+    fn send_output(&mut self, value: isize) {
+        if is_ascii(value) {
+            print!("{}", value as u8 as char);
+        } else {
+            println!("{}", value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    // Greet, prompt for a single character, echo it back, then output a number
+    const GREETER: &str = "
+                OUT  #72
+                OUT  #105
+                OUT  #10
+                OUT  #62
+                OUT  #32
+                IN   c
+                OUT  c
+                OUT  #12345
+                OUT  #10
+                HLT
+        c:      data 0
+    ";
+
+    fn greeter() -> Intcode {
+        Intcode::new(&assemble(GREETER).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn push_line() {
+        let mut machine = Intcode::new("3,0,3,0,3,0,99").unwrap();
+        machine.push_line("ab");
+        assert_eq!(machine.input, vec![97, 98, 10]);
+    }
+
+    #[test]
+    fn read_until_prompt() {
+        let mut machine = greeter();
+        assert_eq!(machine.read_until_prompt("> ").unwrap(), "Hi\n> ");
+
+        machine.push_line("x");
+        assert_eq!(machine.read_until_prompt("> ").unwrap(), "x\n");
+        assert!(machine.is_halted());
+        assert_eq!(machine.get_output(), vec![12345]);
+    }
+
+    #[test]
+    fn take_ascii_output() {
+        let mut machine = greeter();
+        machine.push_line("y");
+        machine.execute().unwrap();
+        assert_eq!(
+            machine.take_ascii_output(),
+            ("Hi\n> y\n".to_string(), vec![12345])
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use crate::instruction::{length, opcode_for, write_operand};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Everything that can go wrong assembling a program. Each variant carries the
+/// (one-based) line number of the offending source line.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum AsmError {
+    /// The mnemonic is not a known instruction or directive
+    UnknownMnemonic { line: usize, mnemonic: String },
+    /// The instruction has the wrong number of operands
+    WrongOperandCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// The operand is neither a number nor a label
+    InvalidOperand { line: usize, operand: String },
+    /// The label is used but never defined
+    UndefinedLabel { line: usize, label: String },
+    /// The label is defined more than once
+    DuplicateLabel { line: usize, label: String },
+    /// The address annotation does not match the address of the line
+    AddressMismatch {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// The instruction writes to an immediate mode operand
+    WriteToImmediate { line: usize },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "Line {}: unknown mnemonic {:?}", line, mnemonic)
+            }
+            AsmError::WrongOperandCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Line {}: expected {} operands, found {}",
+                line, expected, found
+            ),
+            AsmError::InvalidOperand { line, operand } => {
+                write!(f, "Line {}: invalid operand {:?}", line, operand)
+            }
+            AsmError::UndefinedLabel { line, label } => {
+                write!(f, "Line {}: undefined label {:?}", line, label)
+            }
+            AsmError::DuplicateLabel { line, label } => {
+                write!(f, "Line {}: duplicate label {:?}", line, label)
+            }
+            AsmError::AddressMismatch {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Line {}: annotated address {} but actual address is {}",
+                line, found, expected
+            ),
+            AsmError::WriteToImmediate { line } => {
+                write!(f, "Line {}: write to immediate operand", line)
+            }
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// A single line of the program that occupies memory
+enum Item<'a> {
+    Instruction {
+        line: usize,
+        opcode: usize,
+        operands: Vec<&'a str>,
+    },
+    Data {
+        line: usize,
+        values: Vec<&'a str>,
+    },
+}
+
+/// Assemble a program into the comma separated format accepted by `Intcode::new`.
+///
+/// Each line holds at most one instruction, written as a mnemonic (as printed by
+/// the disassembler) followed by comma separated operands. Operands are numbers or
+/// labels, bare for position mode, prefixed with `#` for immediate mode or `@` for
+/// relative mode. A line may be preceded by any number of `label:` definitions, and
+/// `data` lays out literal values. Everything after a `;` is a comment.
+///
+/// Purely numeric labels are treated as address annotations, so the output of the
+/// disassembler can be assembled again as-is.
+pub fn assemble(source: &str) -> Result<String, AsmError> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut address = 0;
+
+    // First pass: lay out the program to find the address of every label
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut rest = text.split(';').next().unwrap_or("").trim();
+
+        // Strip off any label definitions
+        while let Some(colon) = rest.find(':') {
+            let label = rest[..colon].trim();
+            if !label.is_empty() && label.chars().all(|c| c.is_ascii_digit()) {
+                let found = label.parse().map_err(|_| AsmError::InvalidOperand {
+                    line,
+                    operand: label.to_string(),
+                })?;
+                if found != address {
+                    return Err(AsmError::AddressMismatch {
+                        line,
+                        expected: address,
+                        found,
+                    });
+                }
+            } else if !is_label(label) {
+                return Err(AsmError::InvalidOperand {
+                    line,
+                    operand: label.to_string(),
+                });
+            } else if labels.insert(label, address).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line,
+                    label: label.to_string(),
+                });
+            }
+            rest = rest[colon + 1..].trim();
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        // Split the mnemonic from its operands
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(split) => (&rest[..split], rest[split..].trim()),
+            None => (rest, ""),
+        };
+        let operands: Vec<&str> = if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands.split(',').map(str::trim).collect()
+        };
+
+        if mnemonic.eq_ignore_ascii_case("data") {
+            address += operands.len();
+            items.push(Item::Data {
+                line,
+                values: operands,
+            });
+        } else {
+            let opcode = opcode_for(mnemonic).ok_or_else(|| AsmError::UnknownMnemonic {
+                line,
+                mnemonic: mnemonic.to_string(),
+            })?;
+            if operands.len() != length(opcode) - 1 {
+                return Err(AsmError::WrongOperandCount {
+                    line,
+                    expected: length(opcode) - 1,
+                    found: operands.len(),
+                });
+            }
+            address += length(opcode);
+            items.push(Item::Instruction {
+                line,
+                opcode,
+                operands,
+            });
+        }
+    }
+
+    // Second pass: encode each item now that all labels are known
+    let mut words = Vec::new();
+    for item in items {
+        match item {
+            Item::Data { line, values } => {
+                for value in values {
+                    words.push(resolve(value, &labels, line)?);
+                }
+            }
+            Item::Instruction {
+                line,
+                opcode,
+                operands,
+            } => {
+                let mut op_digits = opcode as isize;
+                let mut parameters = Vec::new();
+                let mut place = 100;
+
+                for (i, operand) in operands.iter().enumerate() {
+                    let (mode, value) = if let Some(value) = operand.strip_prefix('#') {
+                        (1, value)
+                    } else if let Some(value) = operand.strip_prefix('@') {
+                        (2, value)
+                    } else {
+                        (0, *operand)
+                    };
+                    if mode == 1 && write_operand(opcode) == Some(i) {
+                        return Err(AsmError::WriteToImmediate { line });
+                    }
+                    op_digits += mode * place;
+                    place *= 10;
+                    parameters.push(resolve(value, &labels, line)?);
+                }
+
+                words.push(op_digits);
+                words.extend(parameters);
+            }
+        }
+    }
+
+    Ok(words
+        .iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+/// Whether the given text is a valid label name
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Resolve an operand or data value to a number, looking up labels as necessary
+fn resolve(value: &str, labels: &HashMap<&str, usize>, line: usize) -> Result<isize, AsmError> {
+    if let Ok(number) = value.parse() {
+        return Ok(number);
+    }
+
+    if !is_label(value) {
+        return Err(AsmError::InvalidOperand {
+            line,
+            operand: value.to_string(),
+        });
+    }
+
+    labels
+        .get(value)
+        .map(|address| *address as isize)
+        .ok_or_else(|| AsmError::UndefinedLabel {
+            line,
+            label: value.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Intcode, RunState};
+
+    fn round_trip(program: &str) {
+        let machine = Intcode::new(program).unwrap();
+        let assembled = assemble(&machine.disassemble()).unwrap();
+        assert_eq!(assembled, machine.memory_string());
+    }
+
+    #[test]
+    fn round_trip_day2_example() {
+        round_trip("1,9,10,3,2,3,11,0,99,30,40,50");
+    }
+
+    #[test]
+    fn round_trip_modes() {
+        round_trip("1002,4,3,4,33");
+    }
+
+    #[test]
+    fn round_trip_quine() {
+        round_trip("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99");
+    }
+
+    #[test]
+    fn labels() {
+        let source = "
+            ; Count down from the input to one
+                    IN   counter
+            loop:   OUT  counter
+                    ADD  counter, #-1, counter
+                    JNZ  counter, #loop
+                    HLT
+            counter: data 0
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program, "3,12,4,12,1001,12,-1,12,1005,12,2,99,0");
+
+        let mut machine = Intcode::new(&program).unwrap();
+        machine.push_input(3);
+        assert_eq!(machine.execute().unwrap(), RunState::Halted);
+        assert_eq!(machine.get_output(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn unknown_mnemonic() {
+        assert_eq!(
+            assemble("ADD 1, 2, 3\nFOO 1"),
+            Err(AsmError::UnknownMnemonic {
+                line: 2,
+                mnemonic: "FOO".into()
+            })
+        );
+    }
+
+    #[test]
+    fn undefined_label() {
+        assert_eq!(
+            assemble("JZ #0, #nowhere"),
+            Err(AsmError::UndefinedLabel {
+                line: 1,
+                label: "nowhere".into()
+            })
+        );
+    }
+
+    #[test]
+    fn wrong_operand_count() {
+        assert_eq!(
+            assemble("MUL 1, 2"),
+            Err(AsmError::WrongOperandCount {
+                line: 1,
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn write_to_immediate() {
+        assert_eq!(
+            assemble("IN #5"),
+            Err(AsmError::WriteToImmediate { line: 1 })
+        );
+    }
+}
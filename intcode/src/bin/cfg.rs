@@ -0,0 +1,33 @@
+use intcode::Intcode;
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    // Read the program file named on the command line
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: cfg <program file>");
+            process::exit(1);
+        }
+    };
+    let s = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", path, e);
+        process::exit(1);
+    });
+    let machine = Intcode::new(&s).unwrap_or_else(|e| {
+        eprintln!("Could not load {}: {}", path, e);
+        process::exit(1);
+    });
+
+    // Print the graph, with anything suspicious noted on stderr
+    let cfg = machine.control_flow();
+    for pointer in cfg.indirect_jumps() {
+        eprintln!("Indirect jump at {}", pointer);
+    }
+    for (pointer, address) in cfg.code_writes() {
+        eprintln!("Instruction at {} writes into code at {}", pointer, address);
+    }
+    print!("{}", cfg.to_dot());
+}
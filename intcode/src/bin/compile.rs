@@ -0,0 +1,40 @@
+use intcode::{CompileError, Intcode};
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    // Read the program file and function name named on the command line
+    let mut args = env::args().skip(1);
+    let (path, name) = match (args.next(), args.next()) {
+        (Some(path), name) => (path, name.unwrap_or_else(|| "program".to_string())),
+        _ => {
+            eprintln!("Usage: compile <program file> [function name]");
+            process::exit(1);
+        }
+    };
+    let s = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", path, e);
+        process::exit(1);
+    });
+    let machine = Intcode::new(&s).unwrap_or_else(|e| {
+        eprintln!("Could not load {}: {}", path, e);
+        process::exit(1);
+    });
+
+    // Print the compiled function, or an interpreter if it can't be compiled
+    match machine.compile(&name) {
+        Ok(source) => print!("{}", source),
+        Err(e @ CompileError::InvalidName { .. }) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}; embedding an interpreter instead", e);
+            let source = machine
+                .compile_or_embed(&name)
+                .expect("the name has already been checked");
+            print!("{}", source);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use intcode::{Debugger, Intcode};
+use std::env;
+use std::io::{self, BufRead, Write};
+
+fn main() {
+    // Start with an empty machine, and load a program if one was named on the command line
+    let mut debugger = Debugger::new(Intcode::new("99").unwrap());
+    if let Some(path) = env::args().nth(1) {
+        println!("{}", debugger.command(&format!("load {}", path)));
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("(icdb) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+
+        match line.trim() {
+            "quit" | "q" => break,
+            command => {
+                let response = debugger.command(command);
+                if !response.is_empty() {
+                    println!("{}", response);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use intcode::Intcode;
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    // Read the program file named on the command line
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: disasm <program file>");
+            process::exit(1);
+        }
+    };
+    let s = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", path, e);
+        process::exit(1);
+    });
+
+    // Parse and print the listing
+    match Intcode::new(&s) {
+        Ok(machine) => print!("{}", machine.disassemble()),
+        Err(e) => {
+            eprintln!("Could not load {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::error::IntcodeError;
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+use crate::word::Word;
+use std::sync::Arc;
+
+// The longest instruction occupies this many cells, so a write can change the
+// decoding of an instruction starting at most this many cells before it.
+const MAX_LENGTH: usize = 4;
+
+/// Instructions already decoded from the tape, indexed by address. An entry is
+/// dropped whenever any of the cells it was decoded from is written, so the
+/// cache never disagrees with memory.
+///
+/// Nothing is cached until the machine has decoded more instructions than a
+/// straight run through the program could need. Short one-shot runs, like the
+/// day 2 search, never revisit an address and would only pay for filling it.
+///
+/// Like memory, the entries are shared between clones until one of them changes.
+#[derive(Debug, Clone)]
+pub(crate) struct DecodeCache {
+    enabled: bool,
+    uncached: usize,
+    entries: Arc<Vec<Option<Instruction>>>,
+}
+
+impl DecodeCache {
+    /// Decode the instruction at the given address, reusing an earlier decoding
+    /// if there is one
+    pub fn decode<W: Word>(
+        &mut self,
+        memory: &Memory<W>,
+        pointer: usize,
+    ) -> Result<Instruction, IntcodeError> {
+        if !self.enabled || pointer >= memory.len() {
+            return Instruction::decode(memory, pointer);
+        }
+        // Every instruction but halt takes at least two cells
+        if self.uncached < memory.len() / 2 {
+            self.uncached += 1;
+            return Instruction::decode(memory, pointer);
+        }
+        if let Some(Some(instruction)) = self.entries.get(pointer) {
+            return Ok(*instruction);
+        }
+
+        let instruction = Instruction::decode(memory, pointer)?;
+        let entries = Arc::make_mut(&mut self.entries);
+        if entries.len() < memory.len() {
+            entries.resize(memory.len(), None);
+        }
+        entries[pointer] = Some(instruction);
+        Ok(instruction)
+    }
+
+    /// Forget any instruction that was decoded from the given address
+    pub fn invalidate(&mut self, address: usize) {
+        self.invalidate_range(address, address.saturating_add(1));
+    }
+
+    /// Forget any instruction that was decoded from memory at or past the given
+    /// length, for when memory shrinks back
+    pub fn truncate(&mut self, len: usize) {
+        self.invalidate_range(len, usize::MAX);
+    }
+
+    /// Forget any instruction that was decoded from an address in `start..end`
+    fn invalidate_range(&mut self, start: usize, end: usize) {
+        let start = start.saturating_sub(MAX_LENGTH - 1);
+        let end = self.entries.len().min(end);
+        // Most writes are to data, so check before taking a copy of shared entries
+        if start >= end || self.entries[start..end].iter().all(Option::is_none) {
+            return;
+        }
+        let entries = Arc::make_mut(&mut self.entries);
+        for entry in &mut entries[start..end] {
+            *entry = None;
+        }
+    }
+
+    /// Turn the cache on or off. Turning it off forgets everything decoded so far.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.entries = Arc::default();
+        }
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            uncached: 0,
+            entries: Arc::default(),
+        }
+    }
+}
+
+// The cache is derived entirely from memory, so it never makes two machines different
+impl PartialEq for DecodeCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for DecodeCache {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache that is past its warm up, so it stores every decoding
+    fn warm() -> DecodeCache {
+        DecodeCache {
+            uncached: usize::MAX,
+            ..DecodeCache::default()
+        }
+    }
+
+    #[test]
+    fn write_invalidates_covering_instructions() {
+        let mut memory = Memory::<isize>::from(vec![1, 0, 0, 0, 99]);
+        let mut cache = warm();
+        assert_eq!(cache.decode(&memory, 0).unwrap().opcode(), 1);
+
+        // Overwrite the last parameter, then the opcode itself
+        memory.write(3, 4);
+        cache.invalidate(3);
+        assert!(cache.entries[0].is_none());
+
+        cache.decode(&memory, 0).unwrap();
+        memory.write(0, 2);
+        cache.invalidate(0);
+        assert_eq!(cache.decode(&memory, 0).unwrap().opcode(), 2);
+    }
+
+    #[test]
+    fn write_after_instruction_keeps_entry() {
+        let memory = Memory::<isize>::from(vec![1, 0, 0, 0, 99]);
+        let mut cache = warm();
+        cache.decode(&memory, 0).unwrap();
+        cache.invalidate(4);
+        assert!(cache.entries[0].is_some());
+    }
+
+    #[test]
+    fn truncate_forgets_instructions_past_the_end() {
+        let memory = Memory::<isize>::from(vec![99, 0, 0, 1, 0, 0, 0, 99]);
+        let mut cache = warm();
+        cache.decode(&memory, 0).unwrap();
+        cache.decode(&memory, 3).unwrap();
+        cache.decode(&memory, 7).unwrap();
+        cache.truncate(5);
+        assert!(cache.entries[0].is_some());
+        assert!(cache.entries[3..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn cold_cache_stores_nothing() {
+        let memory = Memory::<isize>::from(vec![1, 0, 0, 0, 99]);
+        let mut cache = DecodeCache::default();
+        cache.decode(&memory, 0).unwrap();
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        let memory = Memory::<isize>::from(vec![1, 0, 0, 0, 99]);
+        let mut cache = warm();
+        cache.set_enabled(false);
+        cache.decode(&memory, 0).unwrap();
+        assert!(cache.entries.is_empty());
+    }
+}
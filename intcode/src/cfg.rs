@@ -0,0 +1,430 @@
+use crate::instruction::{write_operand, Instruction, Mode};
+use crate::Intcode;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How control gets from one basic block to the next
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Edge {
+    /// Execution runs off the end of the block into the next one
+    FallThrough,
+    /// A jump instruction at the end of the block goes there
+    Jump,
+}
+
+/// A straight run of instructions that is only entered at the top and only left
+/// at the bottom
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Block {
+    start: usize,
+    end: usize,
+    instructions: Vec<usize>,
+    successors: Vec<(usize, Edge)>,
+    indirect: bool,
+}
+
+impl Block {
+    /// The address of the first instruction
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The address just past the last instruction
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// The address of every instruction in the block, in order
+    pub fn instructions(&self) -> &[usize] {
+        &self.instructions
+    }
+
+    /// The blocks control can go to next. A successor may be an address that
+    /// doesn't hold a valid instruction.
+    pub fn successors(&self) -> &[(usize, Edge)] {
+        &self.successors
+    }
+
+    /// Whether the block ends in a jump to a computed address, whose targets
+    /// aren't known statically
+    pub fn ends_in_indirect_jump(&self) -> bool {
+        self.indirect
+    }
+}
+
+/// Where a jump instruction goes
+pub(crate) enum Target {
+    Fixed(usize),
+    Negative(isize),
+    Computed,
+}
+
+/// If the instruction is a jump, whether it is always or never taken (if that is
+/// known statically) and where it goes
+pub(crate) fn jump(instruction: &Instruction) -> Option<(Option<bool>, Target)> {
+    let jump_if_zero = match instruction.opcode() {
+        5 => false,
+        6 => true,
+        _ => return None,
+    };
+    let mut parameters = instruction.parameters();
+    let (condition_mode, condition) = parameters.next()?;
+    let (target_mode, target) = parameters.next()?;
+
+    let taken = if condition_mode == Mode::Immediate {
+        Some((condition == 0) == jump_if_zero)
+    } else {
+        None
+    };
+    let target = match target_mode {
+        Mode::Immediate if target >= 0 => Target::Fixed(target as usize),
+        Mode::Immediate => Target::Negative(target),
+        _ => Target::Computed,
+    };
+    Some((taken, target))
+}
+
+/// The control-flow graph of the code reachable from some entry point. This is
+/// the block analysis the compiler was first written with, moved here so it can
+/// be inspected and drawn; `Intcode::compile` builds its blocks from it.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Cfg {
+    entry: usize,
+    instructions: BTreeMap<usize, Instruction>,
+    invalid: BTreeSet<usize>,
+    leaders: BTreeSet<usize>,
+    blocks: BTreeMap<usize, Block>,
+    listing: BTreeMap<usize, String>,
+}
+
+impl Cfg {
+    /// Follow every path of control from the machine's current pointer. Jumps to
+    /// immediate addresses are followed directly. Jumps to computed addresses can
+    /// only land on an address the program itself has stored, which in practice
+    /// is a return address just after some jump, so when there are any such jumps
+    /// the cell after every jump is explored as well.
+    pub(crate) fn new(machine: &Intcode) -> Self {
+        let entry = machine.pointer;
+        let mut cfg = Self {
+            entry,
+            instructions: BTreeMap::new(),
+            invalid: BTreeSet::new(),
+            leaders: BTreeSet::new(),
+            blocks: BTreeMap::new(),
+            listing: BTreeMap::new(),
+        };
+        cfg.leaders.insert(entry);
+
+        let mut after_jumps = Vec::new();
+        let mut computed_jumps = false;
+        let mut pending = vec![entry];
+        loop {
+            while let Some(pointer) = pending.pop() {
+                if cfg.instructions.contains_key(&pointer) || cfg.invalid.contains(&pointer) {
+                    continue;
+                }
+                let instruction = match Instruction::decode(&machine.memory, pointer) {
+                    Ok(instruction) => instruction,
+                    Err(_) => {
+                        cfg.invalid.insert(pointer);
+                        continue;
+                    }
+                };
+                cfg.instructions.insert(pointer, instruction);
+
+                let next = pointer + instruction.length();
+                match jump(&instruction) {
+                    None if instruction.opcode() == 99 => {}
+                    None => pending.push(next),
+                    Some((taken, target)) => {
+                        match target {
+                            Target::Fixed(target) if taken != Some(false) => {
+                                cfg.leaders.insert(target);
+                                pending.push(target);
+                            }
+                            Target::Computed => computed_jumps = true,
+                            _ => {}
+                        }
+                        if taken == Some(true) {
+                            after_jumps.push(next);
+                        } else {
+                            cfg.leaders.insert(next);
+                            pending.push(next);
+                        }
+                    }
+                }
+            }
+
+            // Cells after unconditional jumps are only worth exploring if something
+            // could jump there, and they may well hold data, so they are not
+            // reported as invalid
+            if !computed_jumps || after_jumps.is_empty() {
+                break;
+            }
+            for pointer in after_jumps.drain(..) {
+                if Instruction::decode(&machine.memory, pointer).is_ok() {
+                    cfg.leaders.insert(pointer);
+                    pending.push(pointer);
+                }
+            }
+        }
+
+        for pointer in cfg.instructions.keys() {
+            cfg.listing
+                .insert(*pointer, machine.disassemble_at(*pointer).0);
+        }
+        let blocks = cfg
+            .leaders
+            .iter()
+            .filter_map(|leader| cfg.build_block(*leader))
+            .map(|block| (block.start, block))
+            .collect();
+        cfg.blocks = blocks;
+        cfg
+    }
+
+    /// Gather the instructions from a leader up to the end of its block
+    fn build_block(&self, start: usize) -> Option<Block> {
+        let mut block = Block {
+            start,
+            end: start,
+            instructions: Vec::new(),
+            successors: Vec::new(),
+            indirect: false,
+        };
+        let mut pointer = start;
+        loop {
+            let instruction = self.instructions.get(&pointer)?;
+            block.instructions.push(pointer);
+            pointer += instruction.length();
+            block.end = pointer;
+
+            if instruction.opcode() == 99 {
+                return Some(block);
+            }
+            if let Some((taken, target)) = jump(instruction) {
+                match target {
+                    Target::Fixed(target) if taken != Some(false) => {
+                        block.successors.push((target, Edge::Jump))
+                    }
+                    Target::Computed => block.indirect = true,
+                    _ => {}
+                }
+                if taken != Some(true) {
+                    block.successors.push((pointer, Edge::FallThrough));
+                }
+                return Some(block);
+            }
+            if self.leaders.contains(&pointer) || self.invalid.contains(&pointer) {
+                block.successors.push((pointer, Edge::FallThrough));
+                return Some(block);
+            }
+        }
+    }
+
+    /// The address control flow was followed from
+    pub fn entry(&self) -> usize {
+        self.entry
+    }
+
+    /// The basic blocks, in address order
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// The basic block starting at the given address, if there is one
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    /// Addresses that control can reach but that don't hold a valid instruction
+    pub fn invalid(&self) -> impl Iterator<Item = usize> + '_ {
+        self.invalid.iter().copied()
+    }
+
+    /// The address of every jump to a computed address
+    pub fn indirect_jumps(&self) -> Vec<usize> {
+        self.blocks
+            .values()
+            .filter(|block| block.indirect)
+            .filter_map(|block| block.instructions.last().copied())
+            .collect()
+    }
+
+    /// Every instruction that writes to a fixed address holding code, along with
+    /// that address. Writes through relative addresses aren't known statically, so
+    /// they are not included.
+    pub fn code_writes(&self) -> Vec<(usize, usize)> {
+        let code = self.code_cells();
+        self.instructions
+            .iter()
+            .filter_map(|(pointer, instruction)| {
+                let (mode, parameter) = instruction
+                    .parameters()
+                    .nth(write_operand(instruction.opcode())?)?;
+                if mode == Mode::Position && parameter >= 0 && code.contains(&(parameter as usize))
+                {
+                    Some((*pointer, parameter as usize))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Every cell occupied by a reachable instruction. Reachable cells that don't
+    /// hold a valid instruction count too, since the program may be patching them.
+    pub fn code_cells(&self) -> BTreeSet<usize> {
+        self.instructions
+            .iter()
+            .flat_map(|(pointer, instruction)| *pointer..pointer + instruction.length())
+            .chain(self.invalid.iter().copied())
+            .collect()
+    }
+
+    /// The decoded instruction at the given address, if it is reachable
+    pub(crate) fn instruction(&self, address: usize) -> Option<&Instruction> {
+        self.instructions.get(&address)
+    }
+
+    /// Addresses where a basic block starts, including reachable invalid ones
+    pub(crate) fn leaders(&self) -> &BTreeSet<usize> {
+        &self.leaders
+    }
+
+    /// Render the graph in Graphviz DOT format. Each block is a box listing its
+    /// instructions. Indirect jumps lead to a `?` node, blocks that write into
+    /// code are red with a dashed edge to the block they write into, and invalid
+    /// addresses are red octagons.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph intcode {\n    node [shape=box fontname=monospace];\n");
+        let code_writes = self.code_writes();
+        let block_of = |address: usize| {
+            self.blocks
+                .range(..=address)
+                .next_back()
+                .map(|(start, _)| *start)
+                .filter(|start| address < self.blocks[start].end)
+        };
+        let writers = code_writes
+            .iter()
+            .filter_map(|(pointer, _)| block_of(*pointer))
+            .collect::<BTreeSet<_>>();
+
+        for block in self.blocks.values() {
+            let label = block
+                .instructions
+                .iter()
+                .map(|address| format!("{:>5}: {}\\l", address, self.listing[address]))
+                .collect::<String>();
+            let color = if writers.contains(&block.start) {
+                " color=red"
+            } else {
+                ""
+            };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, color).unwrap();
+        }
+        for address in &self.invalid {
+            writeln!(
+                dot,
+                "    b{} [label=\"{}: invalid\" shape=octagon color=red];",
+                address, address
+            )
+            .unwrap();
+        }
+
+        for block in self.blocks.values() {
+            for (successor, edge) in &block.successors {
+                let style = match edge {
+                    Edge::FallThrough => "",
+                    Edge::Jump => " [label=jump]",
+                };
+                writeln!(dot, "    b{} -> b{}{};", block.start, successor, style).unwrap();
+            }
+            if block.indirect {
+                writeln!(
+                    dot,
+                    "    indirect{} [label=\"?\" shape=circle];",
+                    block.start
+                )
+                .unwrap();
+                writeln!(
+                    dot,
+                    "    b{} -> indirect{} [label=indirect style=dotted];",
+                    block.start, block.start
+                )
+                .unwrap();
+            }
+        }
+        for (pointer, address) in code_writes {
+            let target = block_of(address).unwrap_or(address);
+            if let Some(writer) = block_of(pointer) {
+                writeln!(
+                    dot,
+                    "    b{} -> b{} [label=\"writes {}\" style=dashed color=red];",
+                    writer, target, address
+                )
+                .unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl Intcode {
+    /// Split the code reachable from the current pointer into basic blocks
+    pub fn control_flow(&self) -> Cfg {
+        Cfg::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countdown_blocks() {
+        // IN c / loop: ADD c, #-1, c / JNZ c, #loop / OUT c / HLT / c: data 0
+        let machine = Intcode::new("3,12,1001,12,-1,12,1005,12,2,4,12,99,0").unwrap();
+        let cfg = machine.control_flow();
+
+        let starts = cfg.blocks().map(Block::start).collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 2, 9]);
+        assert_eq!(
+            cfg.block(0).unwrap().successors(),
+            &[(2, Edge::FallThrough)]
+        );
+        assert_eq!(
+            cfg.block(2).unwrap().successors(),
+            &[(2, Edge::Jump), (9, Edge::FallThrough)]
+        );
+        assert_eq!(cfg.block(2).unwrap().instructions(), &[2, 6]);
+        assert!(cfg.block(9).unwrap().successors().is_empty());
+        assert!(cfg.indirect_jumps().is_empty());
+        assert!(cfg.code_writes().is_empty());
+    }
+
+    #[test]
+    fn flags_indirect_jumps_and_code_writes() {
+        // IN 4 / JNZ #1, 0 / HLT, where the input overwrites the jump's operand
+        let machine = Intcode::new("3,4,105,1,0,99").unwrap();
+        let cfg = machine.control_flow();
+        assert_eq!(cfg.indirect_jumps(), vec![2]);
+        assert_eq!(cfg.code_writes(), vec![(0, 4)]);
+    }
+
+    #[test]
+    fn dot_export() {
+        let machine = Intcode::new("3,12,1001,12,-1,12,1005,12,2,4,12,99,0").unwrap();
+        let dot = machine.control_flow().to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(
+            dot.contains("    b2 [label=\"    2: ADD  12, #-1, 12\\l    6: JNZ  12, #2\\l\"];\n")
+        );
+        assert!(dot.contains("    b2 -> b2 [label=jump];\n"));
+        assert!(dot.contains("    b2 -> b9;\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,615 @@
+use crate::cfg::{jump, Cfg, Target};
+use crate::instruction::Mode;
+use crate::Intcode;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+/// Why a program could not be compiled to Rust
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum CompileError {
+    /// An instruction writes to a memory cell that holds part of an instruction
+    WritesIntoCode { pointer: usize, address: usize },
+    /// The function name is not a Rust identifier
+    InvalidName { name: String },
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::WritesIntoCode { pointer, address } => write!(
+                f,
+                "Instruction at {} writes into code at address {}",
+                pointer, address
+            ),
+            CompileError::InvalidName { name } => {
+                write!(f, "{:?} is not a valid function name", name)
+            }
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+// Words that can't be used as a function name
+const KEYWORDS: &[&str] = &[
+    "Self", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Make sure the name can be used as the generated function's name
+fn check_name(name: &str) -> Result<(), CompileError> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && name != "_"
+                && !KEYWORDS.contains(&name)
+        }
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(CompileError::InvalidName {
+            name: name.to_string(),
+        })
+    }
+}
+
+/// The Rust expression for the value of a parameter
+fn value(mode: Mode, parameter: isize) -> String {
+    match mode {
+        Mode::Position => format!("memory.read({})", parameter),
+        Mode::Immediate => format!("{}", parameter),
+        Mode::Relative => format!("memory.read(relative_base + {})", parameter),
+    }
+}
+
+/// The Rust statement that writes a value to the cell a parameter refers to.
+/// Relative addresses are not known statically, so those writes are checked.
+fn store(mode: Mode, parameter: isize, value: &str) -> String {
+    match mode {
+        Mode::Relative => format!(
+            "memory.write_checked(relative_base + {}, {});",
+            parameter, value
+        ),
+        _ => format!("memory.write({}, {});", parameter, value),
+    }
+}
+
+/// Merge the code cells into inclusive ranges
+fn code_ranges(cfg: &Cfg) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for cell in cfg.code_cells() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cell => *end = cell,
+            _ => ranges.push((cell, cell)),
+        }
+    }
+    ranges
+}
+
+// Memory for generated functions, matching the interpreter: reads past the end
+// are zero, and huge addresses are kept in a map.
+const MEMORY_SOURCE: &str = "    struct Memory {
+        dense: Vec<isize>,
+        sparse: std::collections::HashMap<usize, isize>,
+    }
+
+    impl Memory {
+        fn read(&self, address: isize) -> isize {
+            let address = to_address(address);
+            match self.dense.get(address) {
+                Some(value) => *value,
+                None => *self.sparse.get(&address).unwrap_or(&0),
+            }
+        }
+
+        fn write(&mut self, address: isize, value: isize) {
+            let address = to_address(address);
+            if address >= self.dense.len() && address < 1 << 20 {
+                self.dense.resize(address + 1, 0);
+            }
+            match self.dense.get_mut(address) {
+                Some(cell) => *cell = value,
+                None => {
+                    self.sparse.insert(address, value);
+                }
+            }
+        }
+
+        fn write_checked(&mut self, address: isize, value: isize) {
+            let cell = to_address(address);
+            if CODE.iter().any(|(start, end)| (*start..=*end).contains(&cell)) {
+                panic!(\"Write into code at address {}\", cell);
+            }
+            self.write(address, value);
+        }
+    }
+
+    fn to_address(address: isize) -> usize {
+        if address < 0 {
+            panic!(\"Negative address {}\", address);
+        }
+        address as usize
+    }
+";
+
+// Arithmetic for generated functions when the machine has checked arithmetic
+// on, panicking where the interpreter would stop with an overflow error
+const CHECKED_ARITHMETIC_SOURCE: &str = "    fn add(a: isize, b: isize, pc: usize) -> isize {
+        a.checked_add(b)
+            .unwrap_or_else(|| panic!(\"Overflow at pointer {}\", pc))
+    }
+
+    fn mul(a: isize, b: isize, pc: usize) -> isize {
+        a.checked_mul(b)
+            .unwrap_or_else(|| panic!(\"Overflow at pointer {}\", pc))
+    }
+";
+
+// Arithmetic for generated functions when the machine has checked arithmetic off
+const WRAPPING_ARITHMETIC_SOURCE: &str = "    fn add(a: isize, b: isize, pc: usize) -> isize {
+        a.wrapping_add(b)
+    }
+
+    fn mul(a: isize, b: isize, pc: usize) -> isize {
+        a.wrapping_mul(b)
+    }
+";
+
+// The body of a generated function that interprets its program instead
+const INTERPRETER_SOURCE: &str =
+    "    fn location(memory: &Memory, pc: usize, relative_base: isize, operand: usize) -> isize {
+        let op = memory.read(pc as isize);
+        let mode = op / [100, 1000, 10000][operand] % 10;
+        let parameter = pc as isize + operand as isize + 1;
+        match mode {
+            0 => memory.read(parameter),
+            1 => parameter,
+            2 => relative_base + memory.read(parameter),
+            _ => panic!(\"Invalid mode {} at {}\", mode, pc),
+        }
+    }
+
+    loop {
+        let at = |operand| location(&memory, pc, relative_base, operand);
+        let op = memory.read(pc as isize);
+        match op % 100 {
+            1 | 2 | 7 | 8 => {
+                let a = memory.read(at(0));
+                let b = memory.read(at(1));
+                let result = match op % 100 {
+                    1 => add(a, b, pc),
+                    2 => mul(a, b, pc),
+                    7 => (a < b) as isize,
+                    _ => (a == b) as isize,
+                };
+                let destination = at(2);
+                memory.write(destination, result);
+                pc += 4;
+            }
+            3 => {
+                let destination = at(0);
+                memory.write(destination, input());
+                pc += 2;
+            }
+            4 => {
+                output(memory.read(at(0)));
+                pc += 2;
+            }
+            5 | 6 => {
+                let condition = memory.read(at(0)) != 0;
+                let target = memory.read(at(1));
+                if condition == (op % 100 == 5) {
+                    pc = to_address(target);
+                } else {
+                    pc += 3;
+                }
+            }
+            9 => {
+                relative_base = relative_base
+                    .checked_add(memory.read(at(0)))
+                    .unwrap_or_else(|| panic!(\"Overflow at pointer {}\", pc));
+                pc += 2;
+            }
+            99 => return,
+            _ => panic!(\"Invalid opcode {} at {}\", op, pc),
+        }
+    }
+";
+
+impl Intcode {
+    /// Translate the program into the source of a standalone Rust function with the
+    /// given name. The function takes an input closure and an output closure, and
+    /// runs the program from the current pointer and relative base until it halts.
+    ///
+    /// The program is split into basic blocks, and the function is a loop over a
+    /// `match pc` with one arm per block. This relies on the code never changing, so
+    /// programs with instructions that write into code are refused. Writes through
+    /// relative addresses can't be checked ahead of time, so they panic if they
+    /// land on code. Computed jumps that land anywhere other than the start of a
+    /// block carry on in an interpreter embedded in the function. Addition and
+    /// multiplication panic on overflow if the machine has checked arithmetic on,
+    /// and wrap if it is off, matching the interpreter.
+    pub fn compile(&self, name: &str) -> Result<String, CompileError> {
+        check_name(name)?;
+        let cfg = self.control_flow();
+        if let Some((pointer, address)) = cfg.code_writes().first() {
+            return Err(CompileError::WritesIntoCode {
+                pointer: *pointer,
+                address: *address,
+            });
+        }
+
+        let mut blocks = String::new();
+        for leader in cfg.leaders() {
+            writeln!(blocks, "            {} => {{", leader).unwrap();
+            self.compile_block(&cfg, *leader, &mut blocks);
+            blocks.push_str("            }\n");
+        }
+
+        let ranges = code_ranges(&cfg)
+            .iter()
+            .map(|(start, end)| format!("({}, {})", start, end))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut source = self.function_header(name, &ranges);
+        writeln!(source, "    let mut pc: usize = {};", self.pointer).unwrap();
+        source.push_str("    loop {\n        match pc {\n");
+        source.push_str(&blocks);
+        if cfg.indirect_jumps().is_empty() {
+            source.push_str("            _ => unreachable!(),\n");
+        } else {
+            // A computed jump may land part way into a block, so carry on from
+            // there in the interpreter
+            source.push_str("            _ => {\n");
+            for line in INTERPRETER_SOURCE.lines() {
+                if !line.is_empty() {
+                    writeln!(source, "            {}", line).unwrap();
+                } else {
+                    source.push('\n');
+                }
+            }
+            source.push_str("            }\n");
+        }
+        source.push_str("        }\n    }\n}\n");
+        Ok(source)
+    }
+
+    /// Like `compile`, but programs that can't be compiled become a function that
+    /// interprets them instead. Only fails if the name is not a Rust identifier.
+    pub fn compile_or_embed(&self, name: &str) -> Result<String, CompileError> {
+        check_name(name)?;
+        Ok(self.compile(name).unwrap_or_else(|_| {
+            let mut source = self.function_header(name, "");
+            writeln!(source, "    let mut pc: usize = {};", self.pointer).unwrap();
+            source.push('\n');
+            source.push_str(INTERPRETER_SOURCE);
+            source.push_str("}\n");
+            source
+        }))
+    }
+
+    /// The signature of a generated function, along with its memory and relative base.
+    /// The code ranges are where relative writes are not allowed to land.
+    fn function_header(&self, name: &str, code_ranges: &str) -> String {
+        let mut source = String::new();
+        source.push_str("#[allow(dead_code, unused_mut, unused_variables)]\n");
+        writeln!(
+            source,
+            "pub fn {}(mut input: impl FnMut() -> isize, mut output: impl FnMut(isize)) {{",
+            name
+        )
+        .unwrap();
+        source.push_str(MEMORY_SOURCE);
+        source.push('\n');
+        if self.checked {
+            source.push_str(CHECKED_ARITHMETIC_SOURCE);
+        } else {
+            source.push_str(WRAPPING_ARITHMETIC_SOURCE);
+        }
+        source.push('\n');
+        writeln!(
+            source,
+            "    const CODE: &[(usize, usize)] = &[{}];",
+            code_ranges
+        )
+        .unwrap();
+
+        source.push_str("    let mut memory = Memory {\n        dense: vec![");
+        for (i, value) in self.memory.iter().enumerate() {
+            if i % 16 == 0 {
+                source.push_str("\n            ");
+            } else {
+                source.push(' ');
+            }
+            write!(source, "{},", value).unwrap();
+        }
+        source
+            .push_str("\n        ],\n        sparse: std::collections::HashMap::new(),\n    };\n");
+        writeln!(
+            source,
+            "    let mut relative_base: isize = {};",
+            self.relative_base
+        )
+        .unwrap();
+        source
+    }
+
+    /// Append the statements of the basic block starting at the given address
+    fn compile_block(&self, cfg: &Cfg, leader: usize, out: &mut String) {
+        const INDENT: &str = "                ";
+        let mut pointer = leader;
+        loop {
+            let instruction = match cfg.instruction(pointer) {
+                Some(instruction) => instruction,
+                None => {
+                    writeln!(
+                        out,
+                        "{}panic!(\"Invalid opcode {{}} at {}\", memory.read({}));",
+                        INDENT, pointer, pointer
+                    )
+                    .unwrap();
+                    return;
+                }
+            };
+            let operands = instruction.parameters().collect::<Vec<_>>();
+            let operand = |i: usize| value(operands[i].0, operands[i].1);
+
+            match instruction.opcode() {
+                1 | 2 | 7 | 8 => {
+                    let result = match instruction.opcode() {
+                        1 => format!("add({}, {}, {})", operand(0), operand(1), pointer),
+                        2 => format!("mul({}, {}, {})", operand(0), operand(1), pointer),
+                        7 => format!("({} < {}) as isize", operand(0), operand(1)),
+                        _ => format!("({} == {}) as isize", operand(0), operand(1)),
+                    };
+                    let (mode, parameter) = operands[2];
+                    writeln!(out, "{}{}", INDENT, store(mode, parameter, &result)).unwrap();
+                }
+                3 => {
+                    let (mode, parameter) = operands[0];
+                    writeln!(out, "{}{}", INDENT, store(mode, parameter, "input()")).unwrap();
+                }
+                4 => writeln!(out, "{}output({});", INDENT, operand(0)).unwrap(),
+                5 | 6 => {
+                    let (taken, target) =
+                        jump(instruction).expect("jump instructions have two parameters");
+                    let go = match target {
+                        Target::Fixed(target) => format!("pc = {}; continue;", target),
+                        Target::Negative(target) => {
+                            format!("panic!(\"Negative address {}\");", target)
+                        }
+                        Target::Computed => {
+                            format!("pc = to_address({}); continue;", operand(1))
+                        }
+                    };
+                    match taken {
+                        Some(true) => {
+                            writeln!(out, "{}{}", INDENT, go).unwrap();
+                            return;
+                        }
+                        Some(false) => {}
+                        None => {
+                            let test = if instruction.opcode() == 5 {
+                                "!="
+                            } else {
+                                "=="
+                            };
+                            writeln!(out, "{}if {} {} 0 {{ {} }}", INDENT, operand(0), test, go)
+                                .unwrap();
+                        }
+                    }
+                }
+                9 => writeln!(
+                    out,
+                    "{}relative_base = relative_base.checked_add({}).unwrap_or_else(|| \
+                     panic!(\"Overflow at pointer {}\"));",
+                    INDENT,
+                    operand(0),
+                    pointer
+                )
+                .unwrap(),
+                _ => {
+                    writeln!(out, "{}return;", INDENT).unwrap();
+                    return;
+                }
+            }
+
+            pointer += instruction.length();
+            if cfg.leaders().contains(&pointer) {
+                writeln!(out, "{}pc = {};", INDENT, pointer).unwrap();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+
+    /// Build the generated function into a program that feeds it the given input
+    /// and prints its output, run it, and collect the output
+    fn run_compiled(source: &str, name: &str, input: &[isize]) -> Vec<isize> {
+        let directory =
+            std::env::temp_dir().join(format!("intcode-compile-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let main = format!(
+            "{}\nfn main() {{\n    let mut input = vec!{:?}.into_iter();\n    \
+             {}(|| input.next().unwrap(), |value| println!(\"{{}}\", value));\n}}\n",
+            source, input, name
+        );
+        let source_path = directory.join("main.rs");
+        let binary_path = directory.join("main");
+        fs::write(&source_path, main).unwrap();
+
+        let built = Command::new("rustc")
+            .arg("--edition=2018")
+            .arg("-o")
+            .arg(&binary_path)
+            .arg(&source_path)
+            .output()
+            .unwrap();
+        assert!(
+            built.status.success(),
+            "{}",
+            String::from_utf8_lossy(&built.stderr)
+        );
+        let run = Command::new(&binary_path).output().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        String::from_utf8(run.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect()
+    }
+
+    /// Run the program in the interpreter for comparison
+    fn interpret(program: &str, input: &[isize]) -> Vec<isize> {
+        let mut machine = Intcode::new(program).unwrap();
+        for value in input {
+            machine.push_input(*value);
+        }
+        machine.execute().unwrap();
+        machine.take_output().into_iter().collect()
+    }
+
+    #[test]
+    fn splits_blocks_at_jumps() {
+        // IN c / loop: ADD c, #-1, c / JNZ c, #loop / OUT c / HLT / c: data 0
+        let machine = Intcode::new("3,12,1001,12,-1,12,1005,12,2,4,12,99,0").unwrap();
+        let source = machine.compile("countdown").unwrap();
+        assert!(source.contains("            0 => {\n"));
+        assert!(source.contains("            2 => {\n"));
+        assert!(source.contains("            9 => {\n"));
+        assert!(source.contains("if memory.read(12) != 0 { pc = 2; continue; }"));
+        assert!(source.contains("const CODE: &[(usize, usize)] = &[(0, 11)];"));
+    }
+
+    #[test]
+    fn relative_base_adjustment_is_checked() {
+        // ARB #1 / HLT
+        let source = Intcode::new("109,1,99")
+            .unwrap()
+            .compile("adjusts")
+            .unwrap();
+        assert!(source.contains(
+            "relative_base = relative_base.checked_add(1).unwrap_or_else(|| \
+             panic!(\"Overflow at pointer 0\"));"
+        ));
+    }
+
+    #[test]
+    fn refuses_writes_into_code() {
+        let machine = Intcode::new("1,0,0,0,99").unwrap();
+        assert_eq!(
+            machine.compile("modifies"),
+            Err(CompileError::WritesIntoCode {
+                pointer: 0,
+                address: 0
+            })
+        );
+    }
+
+    #[test]
+    fn refuses_invalid_names() {
+        let machine = Intcode::new("99").unwrap();
+        assert!(machine.compile("run_day_9").is_ok());
+        assert!(machine.compile("_private").is_ok());
+        for name in &["", "_", "9lives", "has space", "fn", "main()"] {
+            let error = Err(CompileError::InvalidName {
+                name: name.to_string(),
+            });
+            assert_eq!(machine.compile(name), error);
+            assert_eq!(machine.compile_or_embed(name), error);
+        }
+    }
+
+    #[test]
+    fn refuses_patching_invalid_instruction() {
+        // ADD [0], #-902, 4 turns the invalid instruction at 4 into HLT
+        let machine = Intcode::new("1001,0,-902,4,103").unwrap();
+        assert_eq!(
+            machine.compile("patches"),
+            Err(CompileError::WritesIntoCode {
+                pointer: 0,
+                address: 4
+            })
+        );
+    }
+
+    #[test]
+    #[ignore = "needs rustc on the PATH"]
+    fn compiled_matches_interpreter() {
+        // The countdown, and the day 9 quine which uses relative mode
+        let countdown = "3,12,1001,12,-1,12,1005,12,2,4,12,99,0";
+        let source = Intcode::new(countdown)
+            .unwrap()
+            .compile("countdown")
+            .unwrap();
+        assert_eq!(
+            run_compiled(&source, "countdown", &[1000]),
+            interpret(countdown, &[1000])
+        );
+
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let source = Intcode::new(quine).unwrap().compile("quine").unwrap();
+        assert_eq!(run_compiled(&source, "quine", &[]), interpret(quine, &[]));
+    }
+
+    #[test]
+    #[ignore = "needs rustc on the PATH"]
+    fn arithmetic_overflow() {
+        // ADD #max, #1, x / MUL x, #2, x / OUT x / HLT / x: data 0
+        let program = "1101,9223372036854775807,1,11,1002,11,2,11,4,11,99,0";
+        let mut machine = Intcode::new(program).unwrap();
+        let source = machine.compile("overflows").unwrap();
+        assert!(source.contains("add(9223372036854775807, 1, 0)"));
+        assert!(source.contains("checked_add"));
+        assert_eq!(run_compiled(&source, "overflows", &[]), vec![]);
+
+        machine.set_checked(false);
+        let source = machine.compile("wraps").unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output(), run_compiled(&source, "wraps", &[]));
+    }
+
+    #[test]
+    #[ignore = "needs rustc on the PATH"]
+    fn computed_jump_into_block() {
+        // IN t / JNZ #1, t / OUT #1 / OUT #2 / HLT / t: data 0
+        let program = "3,10,105,1,10,104,1,104,2,99,0";
+        let source = Intcode::new(program).unwrap().compile("computed").unwrap();
+        assert_eq!(run_compiled(&source, "computed", &[5]), vec![1, 2]);
+        // Address 7 is the middle of a block, so the embedded interpreter takes over
+        assert_eq!(
+            run_compiled(&source, "computed", &[7]),
+            interpret(program, &[7])
+        );
+    }
+
+    #[test]
+    #[ignore = "needs rustc on the PATH"]
+    fn embedded_interpreter_matches_interpreter() {
+        // Reads a number, and adds it to the operand of the following output
+        let program = "3,9,1,9,10,7,104,0,99,0,-100";
+        let source = Intcode::new(program)
+            .unwrap()
+            .compile_or_embed("patched")
+            .unwrap();
+        assert!(!source.contains("match pc"));
+        assert_eq!(
+            run_compiled(&source, "patched", &[104]),
+            interpret(program, &[104])
+        );
+    }
+}
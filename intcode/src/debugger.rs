@@ -0,0 +1,666 @@
+use crate::instruction::opcode_for;
+use crate::{Intcode, IntcodeError};
+use std::collections::BTreeSet;
+use std::fs;
+
+// How many instructions the debugger remembers to step back over, unless told
+// otherwise
+const DEFAULT_HISTORY: usize = 10_000;
+
+// The most cells or instructions `dump` and `disasm` will print at once
+const MAX_COUNT: usize = 10_000;
+
+// How many instructions `continue` runs before coming back to the prompt, so a
+// program stuck in a loop doesn't hang the debugger
+const CONTINUE_LIMIT: usize = 1_000_000;
+
+const HELP: &str = "Commands:
+  load <file>              load a program, keeping breakpoints and watchpoints
+  save <file>              save a snapshot of the machine
+  restore <file>           restore a saved snapshot, keeping breakpoints and watchpoints
+  break <addr>             stop before executing the instruction at an address
+  break op <opcode>        stop before executing any instruction with an opcode (number or mnemonic)
+  watch <addr>             stop after any instruction that changes a memory cell
+  delete <addr>            remove the breakpoint and watchpoint at an address
+  delete op <opcode>       remove an opcode breakpoint
+  list                     list breakpoints and watchpoints
+  step [n]                 execute n instructions (default 1)
+  back [n]                 undo the last n instructions (default 1)
+  rewind <addr>            go back to just before the last instruction that wrote to addr
+  history [n]              remember the last n instructions to step back over (default 10000)
+  continue                 run until a breakpoint, watchpoint, halt, or missing input (at most 1000000 instructions)
+  regs                     print the pointer, relative base, and next instruction
+  dump <addr> [count]      print count memory cells starting at addr (default 16, at most 10000)
+  disasm [addr] [count]    disassemble count instructions from addr (default pointer, 8, at most 10000)
+  input <value>...         push values onto the input queue
+  output                   print everything the program has output
+  help                     print this message
+  quit                     exit the debugger";
+
+/// Why the debugger stopped running the machine
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Stop {
+    /// The requested number of steps were executed
+    Stepped,
+    /// The machine is about to execute the instruction at a breakpoint
+    Breakpoint(usize),
+    /// The machine is about to execute an instruction with a watched opcode
+    OpcodeBreakpoint { pointer: usize, opcode: usize },
+    /// The last instruction changed a watched memory cell
+    Watchpoint {
+        address: usize,
+        old: isize,
+        new: isize,
+    },
+    /// The machine reached a halt instruction
+    Halted,
+    /// The machine needs input that has not been provided yet
+    NeedsInput,
+    /// The machine hit an error
+    Error(IntcodeError),
+    /// The machine ran the given number of instructions without stopping
+    StepLimit(usize),
+}
+
+/// An Intcode machine wrapped with breakpoints and watchpoints, which keeps a
+/// history so it can step backwards as well as forwards
+pub struct Debugger {
+    machine: Intcode,
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    // How many instructions to remember, to step back over
+    history: usize,
+    // How much of the output tape has already been shown by `command`
+    shown_output: usize,
+}
+
+impl Debugger {
+    /// Wrap the given machine in a debugger with no breakpoints or watchpoints
+    pub fn new(mut machine: Intcode) -> Self {
+        machine.enable_history(DEFAULT_HISTORY);
+        Self {
+            machine,
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            history: DEFAULT_HISTORY,
+            shown_output: 0,
+        }
+    }
+
+    /// The machine being debugged
+    pub fn machine(&self) -> &Intcode {
+        &self.machine
+    }
+
+    /// Mutable access to the machine being debugged
+    pub fn machine_mut(&mut self) -> &mut Intcode {
+        &mut self.machine
+    }
+
+    /// Remember the last `capacity` instructions executed, to step back over.
+    /// Everything remembered so far is forgotten.
+    pub fn set_history(&mut self, capacity: usize) {
+        self.history = capacity;
+        self.machine.enable_history(capacity);
+    }
+
+    /// Stop before executing the instruction at the given address
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Stop before executing any instruction with the given opcode
+    pub fn add_opcode_breakpoint(&mut self, opcode: usize) {
+        self.opcode_breakpoints.insert(opcode);
+    }
+
+    /// Stop after any instruction that changes the value at the given address
+    pub fn add_watchpoint(&mut self, address: usize) {
+        self.watchpoints.insert(address);
+    }
+
+    /// Remove the breakpoint and watchpoint at the given address, if any
+    pub fn remove(&mut self, address: usize) {
+        self.breakpoints.remove(&address);
+        self.watchpoints.remove(&address);
+    }
+
+    /// Remove the breakpoint on the given opcode, if any
+    pub fn remove_opcode_breakpoint(&mut self, opcode: usize) {
+        self.opcode_breakpoints.remove(&opcode);
+    }
+
+    /// Execute a single instruction, ignoring breakpoints but reporting watchpoints
+    pub fn step(&mut self) -> Stop {
+        if self.machine.is_halted() {
+            return Stop::Halted;
+        }
+
+        let before: Vec<(usize, isize)> = self
+            .watchpoints
+            .iter()
+            .map(|address| (*address, self.machine.read(*address)))
+            .collect();
+
+        match self.machine.step_mut() {
+            Ok(_) => {}
+            Err(IntcodeError::InputExhausted { .. }) => return Stop::NeedsInput,
+            Err(e) => return Stop::Error(e),
+        }
+
+        for (address, old) in before {
+            let new = self.machine.read(address);
+            if new != old {
+                return Stop::Watchpoint { address, old, new };
+            }
+        }
+
+        Stop::Stepped
+    }
+
+    /// Run until a breakpoint, watchpoint, halt, missing input, or error, for at
+    /// most `CONTINUE_LIMIT` instructions. At least one instruction is executed, so
+    /// resuming from a breakpoint makes progress.
+    pub fn resume(&mut self) -> Stop {
+        self.resume_for(CONTINUE_LIMIT)
+    }
+
+    /// Like `resume`, but stops with `Stop::StepLimit` after `limit` instructions
+    pub fn resume_for(&mut self, limit: usize) -> Stop {
+        for _ in 0..limit {
+            let stop = self.step();
+            if stop != Stop::Stepped {
+                return stop;
+            }
+
+            let pointer = self.machine.pointer();
+            if self.breakpoints.contains(&pointer) {
+                return Stop::Breakpoint(pointer);
+            }
+            if let Ok(operation) = self.machine.current_operation() {
+                if self.opcode_breakpoints.contains(&operation.opcode()) {
+                    return Stop::OpcodeBreakpoint {
+                        pointer,
+                        opcode: operation.opcode(),
+                    };
+                }
+            }
+        }
+        Stop::StepLimit(limit)
+    }
+
+    /// Run a single command line, as typed at the debugger prompt, and return the
+    /// text to show in response.
+    pub fn command(&mut self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return String::new(),
+        };
+
+        let result = match command {
+            "help" | "h" => Ok(HELP.to_string()),
+            "load" | "l" => self.load(args),
+            "save" => self.save(args),
+            "restore" => self.restore(args),
+            "break" | "b" => self.break_command(args),
+            "watch" | "w" => parse_address(args.first()).map(|address| {
+                self.add_watchpoint(address);
+                format!("Watchpoint at {}", address)
+            }),
+            "delete" | "del" => self.delete_command(args),
+            "list" => Ok(self.list()),
+            "step" | "s" => self.step_command(args),
+            "back" => self.back_command(args),
+            "rewind" => self.rewind_command(args),
+            "history" => self.history_command(args),
+            "continue" | "c" => {
+                let stop = self.resume();
+                Ok(self.report(stop))
+            }
+            "regs" | "r" => Ok(self.registers()),
+            "dump" | "x" => self.dump(args),
+            "disasm" | "d" => self.disasm(args),
+            "input" | "i" => self.input(args),
+            "output" | "o" => Ok(format!("{:?}", self.machine.get_output())),
+            _ => Err(format!("Unknown command {:?}. Try help.", command)),
+        };
+
+        result.unwrap_or_else(|e| e)
+    }
+
+    fn load(&mut self, args: &[&str]) -> Result<String, String> {
+        let path = args.first().ok_or("Usage: load <file>")?;
+        let s = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        self.machine = Intcode::new(&s).map_err(|e| format!("Could not load {}: {}", path, e))?;
+        self.machine.enable_history(self.history);
+        self.shown_output = 0;
+        Ok(format!("Loaded {}", path))
+    }
+
+    fn save(&self, args: &[&str]) -> Result<String, String> {
+        let path = args.first().ok_or("Usage: save <file>")?;
+        self.machine
+            .save_snapshot(path)
+            .map_err(|e| format!("Could not save {}: {}", path, e))?;
+        Ok(format!("Saved {}", path))
+    }
+
+    fn restore(&mut self, args: &[&str]) -> Result<String, String> {
+        let path = args.first().ok_or("Usage: restore <file>")?;
+        self.machine = Intcode::load_snapshot(path)
+            .map_err(|e| format!("Could not restore {}: {}", path, e))?;
+        self.machine.enable_history(self.history);
+        // Output from before the snapshot was shown in the session that saved it
+        self.shown_output = self.machine.get_output().len();
+        Ok(format!("Restored {}", path))
+    }
+
+    fn break_command(&mut self, args: &[&str]) -> Result<String, String> {
+        if args.first() == Some(&"op") {
+            let opcode = parse_opcode(args.get(1))?;
+            self.add_opcode_breakpoint(opcode);
+            Ok(format!("Breakpoint on opcode {}", opcode))
+        } else {
+            let address = parse_address(args.first())?;
+            self.add_breakpoint(address);
+            Ok(format!("Breakpoint at {}", address))
+        }
+    }
+
+    fn delete_command(&mut self, args: &[&str]) -> Result<String, String> {
+        if args.first() == Some(&"op") {
+            let opcode = parse_opcode(args.get(1))?;
+            self.remove_opcode_breakpoint(opcode);
+            Ok(format!("Removed breakpoint on opcode {}", opcode))
+        } else {
+            let address = parse_address(args.first())?;
+            self.remove(address);
+            Ok(format!("Removed breakpoint and watchpoint at {}", address))
+        }
+    }
+
+    fn list(&self) -> String {
+        format!(
+            "breakpoints: {:?}\nopcode breakpoints: {:?}\nwatchpoints: {:?}",
+            self.breakpoints, self.opcode_breakpoints, self.watchpoints
+        )
+    }
+
+    fn step_command(&mut self, args: &[&str]) -> Result<String, String> {
+        let count = match args.first() {
+            Some(n) => n.parse().map_err(|_| format!("Invalid count {:?}", n))?,
+            None => 1,
+        };
+
+        let mut stop = Stop::Stepped;
+        for _ in 0..count {
+            stop = self.step();
+            if stop != Stop::Stepped {
+                break;
+            }
+        }
+        Ok(self.report(stop))
+    }
+
+    fn back_command(&mut self, args: &[&str]) -> Result<String, String> {
+        let count = match args.first() {
+            Some(n) => n.parse().map_err(|_| format!("Invalid count {:?}", n))?,
+            None => 1,
+        };
+
+        let undone = self.machine.step_back(count);
+        self.forget_undone_output();
+        Ok(format!("Stepped back {}\n{}", undone, self.registers()))
+    }
+
+    fn rewind_command(&mut self, args: &[&str]) -> Result<String, String> {
+        let address = parse_address(args.first())?;
+        let pointer = self
+            .machine
+            .rewind_to_write(address)
+            .ok_or_else(|| format!("No write to {} in the history", address))?;
+        self.forget_undone_output();
+        Ok(format!(
+            "Last write to {} was by the instruction at {}\n{}",
+            address,
+            pointer,
+            self.registers()
+        ))
+    }
+
+    fn history_command(&mut self, args: &[&str]) -> Result<String, String> {
+        let capacity = match args.first() {
+            Some(n) => n.parse().map_err(|_| format!("Invalid count {:?}", n))?,
+            None => DEFAULT_HISTORY,
+        };
+        self.set_history(capacity);
+        Ok(format!("Remembering the last {} instructions", capacity))
+    }
+
+    /// Output that was undone will be shown again when it is produced again
+    fn forget_undone_output(&mut self) {
+        self.shown_output = self.shown_output.min(self.machine.get_output().len());
+    }
+
+    fn registers(&self) -> String {
+        let pointer = self.machine.pointer();
+        let (next, _) = self.machine.disassemble_at(pointer);
+        format!(
+            "pointer: {}\nrelative base: {}\nnext: {:>5}: {}",
+            pointer,
+            self.machine.relative_base(),
+            pointer,
+            next
+        )
+    }
+
+    fn dump(&self, args: &[&str]) -> Result<String, String> {
+        let start = parse_address(args.first())?;
+        let count = parse_count(args.get(1), 16)?;
+        let end = start
+            .checked_add(count)
+            .ok_or_else(|| format!("Address {} is out of range", start))?;
+
+        // Eight cells per row
+        let mut lines = Vec::new();
+        for row_start in (start..end).step_by(8) {
+            let row_end = row_start.saturating_add(8).min(end);
+            let values = (row_start..row_end)
+                .map(|address| format!("{:>8}", self.machine.read(address)))
+                .collect::<String>();
+            lines.push(format!("{:>5}:{}", row_start, values));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn disasm(&self, args: &[&str]) -> Result<String, String> {
+        let mut address = match args.first() {
+            Some(_) => parse_address(args.first())?,
+            None => self.machine.pointer(),
+        };
+        let count = parse_count(args.get(1), 8)?;
+
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let (text, length) = self.machine.disassemble_at(address);
+            let marker = if address == self.machine.pointer() {
+                "=>"
+            } else {
+                "  "
+            };
+            lines.push(format!("{} {:>5}: {}", marker, address, text));
+            address = address
+                .checked_add(length)
+                .ok_or_else(|| format!("Address {} is out of range", address))?;
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn input(&mut self, args: &[&str]) -> Result<String, String> {
+        if args.is_empty() {
+            return Err("Usage: input <value>...".into());
+        }
+        for arg in args {
+            let value = arg
+                .parse()
+                .map_err(|_| format!("Invalid input value {:?}", arg))?;
+            self.machine.push_input(value);
+        }
+        Ok(format!("Pushed {} input values", args.len()))
+    }
+
+    /// Describe why the machine stopped, along with any output produced since the
+    /// last report
+    fn report(&mut self, stop: Stop) -> String {
+        let mut lines = Vec::new();
+
+        let output = self.machine.get_output();
+        for value in output.iter().skip(self.shown_output) {
+            lines.push(format!("output: {}", value));
+        }
+        self.shown_output = output.len();
+
+        lines.push(match stop {
+            Stop::Stepped => self.registers(),
+            Stop::Breakpoint(address) => format!("Breakpoint at {}\n{}", address, self.registers()),
+            Stop::OpcodeBreakpoint { opcode, .. } => {
+                format!("Breakpoint on opcode {}\n{}", opcode, self.registers())
+            }
+            Stop::Watchpoint { address, old, new } => format!(
+                "Watchpoint at {}: {} -> {}\n{}",
+                address,
+                old,
+                new,
+                self.registers()
+            ),
+            Stop::Halted => "Halted".into(),
+            Stop::NeedsInput => "Waiting for input".into(),
+            Stop::Error(e) => format!("Error: {}", e),
+            Stop::StepLimit(limit) => format!(
+                "Still running after {} instructions\n{}",
+                limit,
+                self.registers()
+            ),
+        });
+
+        lines.join("\n")
+    }
+}
+
+fn parse_address(arg: Option<&&str>) -> Result<usize, String> {
+    let arg = arg.ok_or("Missing address")?;
+    arg.parse()
+        .map_err(|_| format!("Invalid address {:?}", arg))
+}
+
+/// Parse how many cells or instructions to print, up to `MAX_COUNT`
+fn parse_count(arg: Option<&&str>, default: usize) -> Result<usize, String> {
+    let count = match arg {
+        Some(n) => n.parse().map_err(|_| format!("Invalid count {:?}", n))?,
+        None => default,
+    };
+    if count > MAX_COUNT {
+        return Err(format!("Count {} is more than {}", count, MAX_COUNT));
+    }
+    Ok(count)
+}
+
+fn parse_opcode(arg: Option<&&str>) -> Result<usize, String> {
+    let arg = arg.ok_or("Missing opcode")?;
+    arg.parse()
+        .ok()
+        .or_else(|| opcode_for(arg))
+        .ok_or_else(|| format!("Invalid opcode {:?}", arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Count down from the input to one, storing the counter at address 12
+    const COUNTDOWN: &str = "3,12,4,12,1001,12,-1,12,1005,12,2,99,0";
+
+    #[test]
+    fn breakpoint() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        debugger.machine_mut().push_input(3);
+        debugger.add_breakpoint(8);
+
+        assert_eq!(debugger.resume(), Stop::Breakpoint(8));
+        assert_eq!(debugger.machine().read(12), 2);
+        assert_eq!(debugger.resume(), Stop::Breakpoint(8));
+        assert_eq!(debugger.machine().read(12), 1);
+    }
+
+    #[test]
+    fn opcode_breakpoint() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        debugger.machine_mut().push_input(3);
+        debugger.add_opcode_breakpoint(4);
+
+        assert_eq!(
+            debugger.resume(),
+            Stop::OpcodeBreakpoint {
+                pointer: 2,
+                opcode: 4
+            }
+        );
+    }
+
+    #[test]
+    fn watchpoint() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        debugger.machine_mut().push_input(2);
+        debugger.add_watchpoint(12);
+
+        assert_eq!(
+            debugger.resume(),
+            Stop::Watchpoint {
+                address: 12,
+                old: 0,
+                new: 2
+            }
+        );
+        assert_eq!(
+            debugger.resume(),
+            Stop::Watchpoint {
+                address: 12,
+                old: 2,
+                new: 1
+            }
+        );
+        assert_eq!(
+            debugger.resume(),
+            Stop::Watchpoint {
+                address: 12,
+                old: 1,
+                new: 0
+            }
+        );
+        assert_eq!(debugger.resume(), Stop::Halted);
+    }
+
+    #[test]
+    fn step_limit() {
+        // JZ #0, #0 loops forever
+        let mut debugger = Debugger::new(Intcode::new("1106,0,0").unwrap());
+        assert_eq!(debugger.resume_for(100), Stop::StepLimit(100));
+        assert_eq!(debugger.machine().steps(), 100);
+        assert_eq!(debugger.resume_for(100), Stop::StepLimit(100));
+        assert_eq!(debugger.machine().steps(), 200);
+    }
+
+    #[test]
+    fn needs_input() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        assert_eq!(debugger.resume(), Stop::NeedsInput);
+        assert_eq!(debugger.machine().pointer(), 0);
+    }
+
+    #[test]
+    fn commands() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        assert_eq!(debugger.command("input 1"), "Pushed 1 input values");
+        assert_eq!(debugger.command("break op out"), "Breakpoint on opcode 4");
+        assert_eq!(
+            debugger.command("continue"),
+            "Breakpoint on opcode 4\npointer: 2\nrelative base: 0\nnext:     2: OUT  12"
+        );
+        assert_eq!(
+            debugger.command("step"),
+            "output: 1\npointer: 4\nrelative base: 0\nnext:     4: ADD  12, #-1, 12"
+        );
+        assert_eq!(
+            debugger.command("dump 10 3"),
+            "   10:       2      99       1"
+        );
+        assert_eq!(debugger.command("c"), "Halted");
+    }
+
+    #[test]
+    fn out_of_range_listings() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        let last = usize::MAX.to_string();
+        assert_eq!(
+            debugger.command(&format!("dump {} 2", last)),
+            format!("Address {} is out of range", last)
+        );
+        assert_eq!(
+            debugger.command(&format!("disasm {} 2", last)),
+            format!("Address {} is out of range", last)
+        );
+        assert_eq!(
+            debugger.command("dump 0 1000000000"),
+            "Count 1000000000 is more than 10000"
+        );
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let path = std::env::temp_dir().join(format!("icdb-snapshot-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        debugger.command("input 3");
+        debugger.command("break 8");
+        debugger.command("continue");
+        assert_eq!(
+            debugger.command(&format!("save {}", path)),
+            format!("Saved {}", path)
+        );
+
+        debugger.command("c");
+        assert_eq!(debugger.machine().read(12), 1);
+        assert_eq!(
+            debugger.command(&format!("restore {}", path)),
+            format!("Restored {}", path)
+        );
+        fs::remove_file(path).unwrap();
+        assert_eq!(debugger.machine().read(12), 2);
+        assert_eq!(debugger.machine().history_len(), 0);
+        assert_eq!(
+            debugger.command("c"),
+            "output: 2\nBreakpoint at 8\npointer: 8\nrelative base: 0\nnext:     8: JNZ  12, #2"
+        );
+    }
+
+    #[test]
+    fn time_travel() {
+        let mut debugger = Debugger::new(Intcode::new(COUNTDOWN).unwrap());
+        debugger.command("input 2");
+        debugger.command("step 4");
+        assert_eq!(debugger.machine().read(12), 1);
+
+        // Undo the JNZ and ADD, leaving the output from the OUT before them
+        assert_eq!(
+            debugger.command("back 2"),
+            "Stepped back 2\npointer: 4\nrelative base: 0\nnext:     4: ADD  12, #-1, 12"
+        );
+        assert_eq!(debugger.machine().read(12), 2);
+        assert_eq!(debugger.machine().get_output(), vec![2]);
+
+        assert_eq!(
+            debugger.command("rewind 12"),
+            "Last write to 12 was by the instruction at 0\npointer: 0\nrelative base: 0\nnext:     0: IN   12"
+        );
+        assert_eq!(
+            debugger.command("rewind 12"),
+            "No write to 12 in the history"
+        );
+        assert_eq!(
+            debugger.command("step"),
+            "pointer: 2\nrelative base: 0\nnext:     2: OUT  12"
+        );
+        assert_eq!(debugger.machine().read(12), 2);
+
+        // A shorter history only reaches back so far
+        assert_eq!(
+            debugger.command("history 1"),
+            "Remembering the last 1 instructions"
+        );
+        debugger.command("step 2");
+        assert!(debugger.command("back 5").starts_with("Stepped back 1\n"));
+    }
+}
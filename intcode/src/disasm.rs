@@ -0,0 +1,103 @@
+use crate::instruction::{Instruction, Mode};
+use crate::Intcode;
+
+impl Intcode {
+    /// Render the program as an assembly listing, one instruction per line.
+    /// Cells that do not decode as a valid instruction are listed as `DATA`.
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::new();
+        let mut address = 0;
+
+        while address < self.memory.len() {
+            let (text, length) = self.disassemble_at(address);
+            listing.push_str(&format!("{:>5}: {}\n", address, text));
+            address += length;
+        }
+
+        listing
+    }
+
+    /// Disassemble the single instruction at the given address.
+    /// Returns the text of the instruction and the number of cells it occupies.
+    pub fn disassemble_at(&self, address: usize) -> (String, usize) {
+        match Instruction::decode(&self.memory, address) {
+            // An instruction that runs off the end of the program, or has stray mode
+            // digits that would be lost in the listing, is most likely data
+            Ok(instruction)
+                if address + instruction.length() <= self.memory.len()
+                    && instruction.op_digits() == self.memory.read(address) =>
+            {
+                let operands = instruction
+                    .parameters()
+                    .map(|(mode, parameter)| format_parameter(mode, parameter))
+                    .collect::<Vec<_>>();
+
+                let text = if operands.is_empty() {
+                    instruction.mnemonic().to_string()
+                } else {
+                    format!("{:<4} {}", instruction.mnemonic(), operands.join(", "))
+                };
+                (text, instruction.length())
+            }
+            _ => (format!("DATA {}", self.memory.read(address)), 1),
+        }
+    }
+}
+
+/// Format a parameter with the prefix for its mode. Position mode parameters
+/// are bare, immediate mode are prefixed with `#` and relative mode with `@`.
+fn format_parameter(mode: Mode, parameter: isize) -> String {
+    match mode {
+        Mode::Position => format!("{}", parameter),
+        Mode::Immediate => format!("#{}", parameter),
+        Mode::Relative => format!("@{}", parameter),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day2_example() {
+        let machine = Intcode::new("1,9,10,3,2,3,11,0,99,30,40,50").unwrap();
+        let expected = "    0: ADD  9, 10, 3
+    4: MUL  3, 11, 0
+    8: HLT
+    9: DATA 30
+   10: DATA 40
+   11: DATA 50
+";
+        assert_eq!(machine.disassemble(), expected);
+    }
+
+    #[test]
+    fn parameter_modes() {
+        let machine = Intcode::new("1002,4,3,4,109,-1,204,1,99").unwrap();
+        let expected = "    0: MUL  4, #3, 4
+    4: ARB  #-1
+    6: OUT  @1
+    8: HLT
+";
+        assert_eq!(machine.disassemble(), expected);
+    }
+
+    #[test]
+    fn truncated_instruction_is_data() {
+        let machine = Intcode::new("99,1,2").unwrap();
+        let expected = "    0: HLT
+    1: DATA 1
+    2: DATA 2
+";
+        assert_eq!(machine.disassemble(), expected);
+    }
+
+    #[test]
+    fn stray_modes_are_data() {
+        let machine = Intcode::new("99999,104,5").unwrap();
+        let expected = "    0: DATA 99999
+    1: OUT  #5
+";
+        assert_eq!(machine.disassemble(), expected);
+    }
+}
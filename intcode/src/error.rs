@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt;
+
+/// Everything that can go wrong loading or running an Intcode program
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum IntcodeError {
+    /// The value at the given offset in the program text is not an integer
+    Parse { offset: usize, value: String },
+    /// The instruction at the given pointer has an unknown opcode
+    InvalidOpcode { pointer: usize, opcode: isize },
+    /// The instruction at the given pointer has an unknown parameter mode
+    InvalidMode { pointer: usize, mode: usize },
+    /// The input instruction at the given pointer has no input to consume
+    InputExhausted { pointer: usize },
+    /// The instruction at the given pointer refers to a negative address
+    NegativeAddress { pointer: usize, address: isize },
+    /// The instruction at the given pointer writes to an immediate mode operand
+    WriteToImmediate { pointer: usize },
+    /// The instruction at the given pointer overflowed in checked mode, or used a
+    /// value too large to be an address or opcode
+    Overflow { pointer: usize },
+    /// The machine was stopped at the given pointer after executing its limit of
+    /// instructions
+    StepLimit { pointer: usize, limit: u64 },
+    /// The machine came back to a state it had been in before, so it will repeat
+    /// the `length` instructions from `start` forever
+    InfiniteLoop { start: usize, length: u64 },
+    /// A replayed machine stopped matching its recording at the given pointer
+    /// and step
+    Diverged { pointer: usize, step: u64 },
+    /// The machine at the given network address sent a packet to a negative
+    /// destination
+    NegativeDestination { machine: usize, destination: isize },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::Parse { offset, value } => {
+                write!(f, "Could not parse {:?} at offset {}", value, offset)
+            }
+            IntcodeError::InvalidOpcode { pointer, opcode } => {
+                write!(f, "Invalid opcode {} at pointer {}", opcode, pointer)
+            }
+            IntcodeError::InvalidMode { pointer, mode } => {
+                write!(f, "Invalid parameter mode {} at pointer {}", mode, pointer)
+            }
+            IntcodeError::InputExhausted { pointer } => {
+                write!(f, "No input available at pointer {}", pointer)
+            }
+            IntcodeError::NegativeAddress { pointer, address } => {
+                write!(f, "Negative address {} at pointer {}", address, pointer)
+            }
+            IntcodeError::WriteToImmediate { pointer } => {
+                write!(f, "Write to immediate operand at pointer {}", pointer)
+            }
+            IntcodeError::Overflow { pointer } => {
+                write!(f, "Overflow at pointer {}", pointer)
+            }
+            IntcodeError::StepLimit { pointer, limit } => {
+                write!(f, "Step limit of {} reached at pointer {}", limit, pointer)
+            }
+            IntcodeError::InfiniteLoop { start, length } => write!(
+                f,
+                "Infinite loop of {} instructions starting at {}",
+                length, start
+            ),
+            IntcodeError::Diverged { pointer, step } => write!(
+                f,
+                "Replay diverged from the recording at step {}, pointer {}",
+                step, pointer
+            ),
+            IntcodeError::NegativeDestination {
+                machine,
+                destination,
+            } => write!(
+                f,
+                "Machine {} sent a packet to negative address {}",
+                machine, destination
+            ),
+        }
+    }
+}
+
+impl Error for IntcodeError {}
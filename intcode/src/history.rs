@@ -0,0 +1,282 @@
+use crate::instruction::write_operand;
+use crate::{Intcode, Operation, Word};
+use std::collections::VecDeque;
+
+/// What a single instruction changed, holding the values it replaced so that it
+/// can be undone
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub(crate) struct Change<W> {
+    pointer: usize,
+    relative_base: isize,
+    // The address the instruction wrote to, and the value that was there before.
+    // The value is `None` if the address had no cell yet.
+    write: Option<(usize, Option<W>)>,
+    // How long the contiguous part of memory was before the instruction
+    memory_len: usize,
+    // The input value the instruction consumed
+    input: Option<W>,
+    // How long the output queue was before the instruction
+    output_len: usize,
+}
+
+impl<W: Word> Change<W> {
+    /// Record the state an operation that is about to be executed will change
+    pub(crate) fn before(machine: &Intcode<W>, operation: &Operation) -> Self {
+        let write = write_operand(operation.opcode()).map(|i| {
+            let address = operation.operand_locations()[i];
+            let old = if machine.memory.contains(address) {
+                Some(machine.memory.read(address))
+            } else {
+                None
+            };
+            (address, old)
+        });
+        let input = if operation.opcode() == 3 {
+            machine.input.front().cloned()
+        } else {
+            None
+        };
+        Self {
+            pointer: machine.pointer,
+            relative_base: machine.relative_base,
+            write,
+            memory_len: machine.memory.len(),
+            input,
+            output_len: machine.output.len(),
+        }
+    }
+}
+
+/// An undo log of the most recent instructions a machine executed
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub(crate) struct History<W> {
+    capacity: usize,
+    changes: VecDeque<Change<W>>,
+}
+
+impl<W> History<W> {
+    /// Add the change made by the latest instruction, dropping the oldest one if
+    /// the log is full
+    pub(crate) fn push(&mut self, change: Change<W>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(change);
+    }
+}
+
+impl<W: Word> Intcode<W> {
+    /// Start keeping an undo log of the last `capacity` instructions executed, so
+    /// the machine can be stepped backwards. Any previous log is discarded.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History {
+            capacity,
+            changes: VecDeque::new(),
+        });
+    }
+
+    /// Stop keeping an undo log, discarding the one kept so far
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// How many instructions can currently be undone
+    pub fn history_len(&self) -> usize {
+        self.history
+            .as_ref()
+            .map_or(0, |history| history.changes.len())
+    }
+
+    /// Step backwards up to `count` instructions, restoring memory, the pointer,
+    /// the relative base, and the input and output queues. Output that was
+    /// already taken from the machine can't be restored. Undone steps are removed
+    /// from the trace and input recording too. Returns how many
+    /// instructions were undone, which is fewer than `count` if the log runs out.
+    pub fn step_back(&mut self, count: usize) -> usize {
+        for undone in 0..count {
+            if !self.undo() {
+                return undone;
+            }
+        }
+        count
+    }
+
+    /// Step backwards to just before the most recent logged instruction that
+    /// wrote to the given address, and return the address of that instruction.
+    /// If no logged instruction wrote there, the machine is left as it is.
+    pub fn rewind_to_write(&mut self, address: usize) -> Option<usize> {
+        let history = self.history.as_ref()?;
+        let index = history
+            .changes
+            .iter()
+            .rposition(|change| matches!(change.write, Some((a, _)) if a == address))?;
+        self.step_back(history.changes.len() - index);
+        Some(self.pointer)
+    }
+
+    /// Undo the most recent logged instruction, if there is one
+    fn undo(&mut self) -> bool {
+        let change = match self.history.as_mut().and_then(|h| h.changes.pop_back()) {
+            Some(change) => change,
+            None => return false,
+        };
+
+        self.forget_states();
+        match change.write {
+            Some((address, Some(value))) => self.write(address, value),
+            Some((address, None)) => {
+                self.write(address, W::from_isize(0));
+                self.memory.remove(address);
+            }
+            None => {}
+        }
+        self.memory.truncate(change.memory_len);
+        self.decoded.truncate(change.memory_len);
+        if let Some(value) = change.input {
+            self.input.push_front(value);
+        }
+        self.output.truncate(change.output_len);
+        self.pointer = change.pointer;
+        self.relative_base = change.relative_base;
+        self.steps -= 1;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.unrecord(self.steps);
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            recording.unrecord(self.steps);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    // IN c / loop: ADD c, #-1, c / JNZ c, #loop / OUT c / HLT / c: data 0
+    const COUNTDOWN: &str = "3,12,1001,12,-1,12,1005,12,2,4,12,99,0";
+
+    #[test]
+    fn step_back_restores_state() {
+        let mut machine = Intcode::new(COUNTDOWN).unwrap();
+        machine.enable_history(100);
+        machine.push_input(3);
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output(), vec![0]);
+        assert_eq!(machine.history_len(), 8);
+
+        // Back over the OUT, then the final ADD and JNZ
+        assert_eq!(machine.step_back(1), 1);
+        assert!(machine.get_output().is_empty());
+        assert_eq!(machine.pointer(), 9);
+        assert_eq!(machine.step_back(2), 2);
+        assert_eq!(machine.pointer(), 2);
+        assert_eq!(machine.read(12), 1);
+
+        // All the way back to before the IN, which puts the input back
+        assert_eq!(machine.step_back(100), 5);
+        assert_eq!(machine.pointer(), 0);
+        assert_eq!(machine.steps(), 0);
+        assert_eq!(machine.read(12), 0);
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output(), vec![0]);
+    }
+
+    #[test]
+    fn rewind_to_write() {
+        // ADD #1, #2, a / MUL a, #2, b / ADD #0, #0, a / HLT / a: data 0 / b: data 0
+        let mut machine = Intcode::new("1101,1,2,13,1002,13,2,14,1101,0,0,13,99,0,0").unwrap();
+        machine.enable_history(100);
+        machine.execute().unwrap();
+
+        assert_eq!(machine.rewind_to_write(14), Some(4));
+        assert_eq!(machine.read(14), 0);
+        assert_eq!(machine.read(13), 3);
+        assert_eq!(machine.rewind_to_write(13), Some(0));
+        assert_eq!(machine.read(13), 0);
+        assert_eq!(machine.rewind_to_write(13), None);
+        assert_eq!(machine.rewind_to_write(3), None);
+    }
+
+    #[test]
+    fn step_back_shrinks_memory() {
+        // ADD #1, #2, 1000 / ADD #3, #4, 1000000000 / HLT
+        let mut machine = Intcode::new("1101,1,2,1000,1101,3,4,1000000000,99").unwrap();
+        machine.enable_history(100);
+        let before = machine.clone();
+        machine.execute().unwrap();
+        assert_eq!(machine.read(1_000_000_000), 7);
+
+        assert_eq!(machine.step_back(2), 2);
+        assert!(machine.memory == before.memory);
+        assert!(machine == before);
+    }
+
+    #[test]
+    fn step_back_over_written_code() {
+        // A countdown from 100 warms up the decode cache, then the program copies
+        // OUT #d1 / HLT past the end of memory and jumps to it
+        let program = assemble(
+            "
+                    IN   n
+            loop:   ADD  n, #-1, n
+                    JNZ  n, #loop
+            code:   ADD  d0, #0, 200
+                    ADD  d1, #0, 201
+                    ADD  #99, #0, 202
+                    JZ   #0, #200
+            n:      data 0
+            d0:     data 104
+            d1:     data 7
+            ",
+        )
+        .unwrap();
+        let mut machine = Intcode::new(&program).unwrap();
+        machine.enable_history(1000);
+        machine.push_input(100);
+        machine.execute().unwrap();
+        assert_eq!(machine.take_output(), vec![7]);
+
+        // Back to before the copy, then run again with a different operand
+        machine.step_back(5);
+        assert_eq!(machine.pointer(), 9);
+        machine.mutate_memory(program.split(',').count() - 1, 9);
+        machine.execute().unwrap();
+        assert_eq!(machine.take_output(), vec![9]);
+    }
+
+    #[test]
+    fn capacity_limits_history() {
+        let mut machine = Intcode::new(COUNTDOWN).unwrap();
+        machine.enable_history(3);
+        machine.push_input(3);
+        machine.execute().unwrap();
+        assert_eq!(machine.history_len(), 3);
+        // Only the last ADD, JNZ and OUT were kept
+        assert_eq!(machine.step_back(10), 3);
+        assert_eq!(machine.pointer(), 2);
+        assert_eq!(machine.read(12), 1);
+    }
+
+    #[test]
+    fn step_back_unrecords_trace() {
+        let mut machine = Intcode::new(COUNTDOWN).unwrap();
+        machine.enable_history(100);
+        machine.enable_trace();
+        machine.push_input(2);
+        machine.execute().unwrap();
+        let trace = machine.trace().unwrap().clone();
+
+        machine.step_back(3);
+        assert_eq!(
+            machine.trace().unwrap().entries().len(),
+            trace.entries().len() - 3
+        );
+        machine.execute().unwrap();
+        assert_eq!(machine.trace(), Some(&trace));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::error::IntcodeError;
+use crate::memory::Memory;
+use crate::word::Word;
+
+// hardcoded lengths of opcodes.
+// opcode 0 is not valid
+// opcodes 1 and 2 have length four (see day 2)
+// opcodes 3 and 4 have length two (see day 5 part 1)
+// opcodes 5 and 6 have length 3 (see day 5 part 2)
+// opcodes 7 and 8 have length 4 (see day 5 part 2)
+// opcode 9 has length 2 (see day 9)
+const LENGTHS: [usize; 10] = [0, 4, 4, 2, 2, 3, 3, 4, 4, 2];
+
+// Which operand, if any, each opcode writes its result to. Those operands may
+// not be in immediate mode.
+const WRITE_OPERANDS: [Option<usize>; 10] = [
+    None,
+    Some(2),
+    Some(2),
+    Some(0),
+    None,
+    None,
+    None,
+    Some(2),
+    Some(2),
+    None,
+];
+
+// Assembly mnemonics for each opcode. The halt instruction (99) is handled separately.
+const MNEMONICS: [&str; 10] = [
+    "", "ADD", "MUL", "IN", "OUT", "JNZ", "JZ", "LT", "EQ", "ARB",
+];
+
+/// How a parameter of an instruction is interpreted
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Mode {
+    Position = 0,
+    Immediate = 1,
+    Relative = 2,
+}
+
+/// An instruction as it appears on the tape, before its parameters are resolved
+/// to memory locations
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Instruction {
+    opcode: usize,
+    modes: [Mode; 3],
+    parameters: [isize; 3],
+}
+
+impl Instruction {
+    /// Decode the instruction at the given address
+    pub fn decode<W: Word>(memory: &Memory<W>, pointer: usize) -> Result<Self, IntcodeError> {
+        let raw = memory.read(pointer);
+        let op_digits = raw.to_isize().ok_or(IntcodeError::Overflow { pointer })?;
+        let opcode = op_digits % 100;
+        let known = opcode == 99 || (1..LENGTHS.len() as isize).contains(&opcode);
+        if op_digits < 0 || !known {
+            return Err(IntcodeError::InvalidOpcode {
+                pointer,
+                opcode: op_digits,
+            });
+        }
+        let opcode = opcode as usize;
+        let mut modes_digits = op_digits as usize / 100;
+
+        // Expected number of operands for this opcode. Knowing this value is
+        // necessary because leading zeros may be omitted
+        let num_operands = length(opcode) - 1;
+
+        let mut modes = [Mode::Position; 3];
+        let mut parameters = [0; 3];
+        for i in 0..num_operands {
+            modes[i] = match modes_digits % 10 {
+                0 => Mode::Position,
+                1 => Mode::Immediate,
+                2 => Mode::Relative,
+                mode => {
+                    return Err(IntcodeError::InvalidMode { pointer, mode });
+                }
+            };
+            if modes[i] == Mode::Immediate && write_operand(opcode) == Some(i) {
+                return Err(IntcodeError::WriteToImmediate { pointer });
+            }
+            // Immediate parameters that don't fit are kept as zero. The interpreter
+            // reads immediate values from memory rather than from here.
+            let parameter = memory.read(pointer + i + 1).to_isize();
+            parameters[i] = match (modes[i], parameter) {
+                (_, Some(parameter)) => parameter,
+                (Mode::Immediate, None) => 0,
+                (_, None) => return Err(IntcodeError::Overflow { pointer }),
+            };
+            modes_digits /= 10;
+        }
+
+        Ok(Self {
+            opcode,
+            modes,
+            parameters,
+        })
+    }
+
+    /// The opcode, with the parameter modes stripped off
+    pub fn opcode(&self) -> usize {
+        self.opcode
+    }
+
+    /// The opcode and parameter modes as they would be written on the tape
+    pub fn op_digits(&self) -> isize {
+        let mut op_digits = self.opcode as isize;
+        let mut place = 100;
+        for (mode, _) in self.parameters() {
+            op_digits += place * mode as isize;
+            place *= 10;
+        }
+        op_digits
+    }
+
+    /// The number of cells this instruction occupies, including the opcode itself
+    pub fn length(&self) -> usize {
+        length(self.opcode)
+    }
+
+    /// The assembly mnemonic for this instruction
+    pub fn mnemonic(&self) -> &'static str {
+        mnemonic(self.opcode).expect("decoded instructions have valid opcodes")
+    }
+
+    /// The raw parameters of this instruction along with their modes
+    pub fn parameters(&self) -> impl Iterator<Item = (Mode, isize)> + '_ {
+        self.modes
+            .iter()
+            .copied()
+            .zip(self.parameters.iter().copied())
+            .take(self.length() - 1)
+    }
+}
+
+/// The number of cells an instruction with the given opcode occupies
+pub fn length(opcode: usize) -> usize {
+    if opcode == 99 {
+        1
+    } else {
+        LENGTHS[opcode]
+    }
+}
+
+/// The operand, if any, that an instruction with the given opcode writes to
+pub fn write_operand(opcode: usize) -> Option<usize> {
+    if opcode == 99 {
+        None
+    } else {
+        WRITE_OPERANDS[opcode]
+    }
+}
+
+/// The assembly mnemonic of the given opcode, if it is valid
+pub fn mnemonic(opcode: usize) -> Option<&'static str> {
+    match opcode {
+        99 => Some("HLT"),
+        1..=9 => Some(MNEMONICS[opcode]),
+        _ => None,
+    }
+}
+
+/// The opcode for the given assembly mnemonic, if it is valid. Mnemonics are
+/// not case sensitive.
+pub fn opcode_for(mnemonic: &str) -> Option<usize> {
+    let mnemonic = mnemonic.to_uppercase();
+    if mnemonic == "HLT" {
+        return Some(99);
+    }
+    MNEMONICS
+        .iter()
+        .skip(1)
+        .position(|m| *m == mnemonic)
+        .map(|i| i + 1)
+}
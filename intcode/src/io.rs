@@ -0,0 +1,241 @@
+use crate::{Intcode, IntcodeError, RunState};
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+
+/// Somewhere an Intcode machine can get its input from
+pub trait InputSource {
+    /// The next input value, or `None` if there is none available right now
+    fn next_input(&mut self) -> Option<isize>;
+}
+
+/// Somewhere an Intcode machine can send its output to
+pub trait OutputSink {
+    /// Accept a single output value
+    fn send_output(&mut self, value: isize);
+}
+
+impl<T: InputSource + ?Sized> InputSource for &mut T {
+    fn next_input(&mut self) -> Option<isize> {
+        (**self).next_input()
+    }
+}
+
+impl<T: OutputSink + ?Sized> OutputSink for &mut T {
+    fn send_output(&mut self, value: isize) {
+        (**self).send_output(value)
+    }
+}
+
+impl<T: InputSource + ?Sized> InputSource for Box<T> {
+    fn next_input(&mut self) -> Option<isize> {
+        (**self).next_input()
+    }
+}
+
+impl<T: OutputSink + ?Sized> OutputSink for Box<T> {
+    fn send_output(&mut self, value: isize) {
+        (**self).send_output(value)
+    }
+}
+
+impl InputSource for VecDeque<isize> {
+    fn next_input(&mut self) -> Option<isize> {
+        self.pop_front()
+    }
+}
+
+impl OutputSink for VecDeque<isize> {
+    fn send_output(&mut self, value: isize) {
+        self.push_back(value);
+    }
+}
+
+impl OutputSink for Vec<isize> {
+    fn send_output(&mut self, value: isize) {
+        self.push(value);
+    }
+}
+
+/// Input taken from any iterator of values
+pub struct IterInput<I>(pub I);
+
+impl<I: Iterator<Item = isize>> InputSource for IterInput<I> {
+    fn next_input(&mut self) -> Option<isize> {
+        self.0.next()
+    }
+}
+
+/// Input produced on demand by a closure
+pub struct InputFn<F>(pub F);
+
+impl<F: FnMut() -> Option<isize>> InputSource for InputFn<F> {
+    fn next_input(&mut self) -> Option<isize> {
+        (self.0)()
+    }
+}
+
+/// Output handed to a closure
+pub struct OutputFn<F>(pub F);
+
+impl<F: FnMut(isize)> OutputSink for OutputFn<F> {
+    fn send_output(&mut self, value: isize) {
+        (self.0)(value)
+    }
+}
+
+/// Input read from stdin, one value per line. Blank lines are skipped, and the
+/// input runs dry at the end of the stream or on a line that is not a number.
+pub struct StdinInput;
+
+impl InputSource for StdinInput {
+    fn next_input(&mut self) -> Option<isize> {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line.ok()?;
+            if !line.trim().is_empty() {
+                return line.trim().parse().ok();
+            }
+        }
+        None
+    }
+}
+
+/// Output printed to stdout, one value per line
+pub struct StdoutOutput;
+
+impl OutputSink for StdoutOutput {
+    fn send_output(&mut self, value: isize) {
+        println!("{}", value);
+    }
+}
+
+/// Waits for a value from the channel. The input runs dry once every sender is gone.
+impl InputSource for Receiver<isize> {
+    fn next_input(&mut self) -> Option<isize> {
+        self.recv().ok()
+    }
+}
+
+/// Output sent after the receiver hangs up is dropped.
+impl OutputSink for Sender<isize> {
+    fn send_output(&mut self, value: isize) {
+        let _ = self.send(value);
+    }
+}
+
+/// Output sent after the receiver hangs up is dropped.
+impl OutputSink for SyncSender<isize> {
+    fn send_output(&mut self, value: isize) {
+        let _ = self.send(value);
+    }
+}
+
+impl Intcode {
+    /// Run this machine wired to the given input source and output sink. Input
+    /// already queued on the machine is consumed first. Output goes straight to the
+    /// sink rather than the output tape. Stops when the machine halts, or when it
+    /// needs input and the source has none available.
+    pub fn run_with<I: InputSource, O: OutputSink>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<RunState, IntcodeError> {
+        loop {
+            match self.run_until_output()? {
+                RunState::Output(value) => output.send_output(value),
+                RunState::NeedsInput => match input.next_input() {
+                    Some(value) => self.push_input(value),
+                    None => return Ok(RunState::NeedsInput),
+                },
+                RunState::Halted => return Ok(RunState::Halted),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    // Output double each input, halting on an input of zero
+    //   loop:  IN   value
+    //          JZ   value, #done
+    //          MUL  value, #2, value
+    //          OUT  value
+    //          JZ   #0, #loop
+    //   done:  HLT
+    //   value: data 0
+    const DOUBLER: &str = "3,15,1006,15,14,1002,15,2,15,4,15,1106,0,0,99,0";
+
+    #[test]
+    fn iterator_to_vec() {
+        let mut machine = Intcode::new(DOUBLER).unwrap();
+        let mut output = Vec::new();
+        let state = machine
+            .run_with(IterInput(vec![1, 2, 3, 0].into_iter()), &mut output)
+            .unwrap();
+        assert_eq!(state, RunState::Halted);
+        assert_eq!(output, vec![2, 4, 6]);
+        assert!(machine.get_output().is_empty());
+    }
+
+    #[test]
+    fn input_runs_dry() {
+        let mut machine = Intcode::new(DOUBLER).unwrap();
+        let mut input: VecDeque<isize> = vec![5].into_iter().collect();
+        let mut output = VecDeque::new();
+        let state = machine.run_with(&mut input, &mut output).unwrap();
+        assert_eq!(state, RunState::NeedsInput);
+        assert_eq!(output, vec![10]);
+
+        // Resume once more input is available
+        input.push_back(0);
+        let state = machine.run_with(&mut input, &mut output).unwrap();
+        assert_eq!(state, RunState::Halted);
+    }
+
+    #[test]
+    fn closures() {
+        let mut machine = Intcode::new(DOUBLER).unwrap();
+        let mut next = 3;
+        let mut total = 0;
+        machine
+            .run_with(
+                InputFn(|| {
+                    next -= 1;
+                    Some(next)
+                }),
+                OutputFn(|value| total += value),
+            )
+            .unwrap();
+        assert_eq!(total, 4 + 2);
+    }
+
+    #[test]
+    fn channels() {
+        let (to_first, first_input) = channel();
+        let (first_output, second_input) = channel();
+        let (second_output, results) = channel();
+
+        let first = thread::spawn(move || {
+            let mut machine = Intcode::new(DOUBLER).unwrap();
+            machine.run_with(first_input, first_output).unwrap()
+        });
+        let second = thread::spawn(move || {
+            let mut machine = Intcode::new(DOUBLER).unwrap();
+            machine.run_with(second_input, second_output).unwrap()
+        });
+
+        for value in &[1, 2, 0] {
+            to_first.send(*value).unwrap();
+        }
+
+        // The first machine halts on the zero, which hangs up the second machine's input
+        assert_eq!(first.join().unwrap(), RunState::Halted);
+        assert_eq!(second.join().unwrap(), RunState::NeedsInput);
+        assert_eq!(results.iter().collect::<Vec<_>>(), vec![4, 8]);
+    }
+}
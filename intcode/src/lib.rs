@@ -1,139 +1,448 @@
 use std::collections::VecDeque;
 
-// hardcoded lengths of opcodes.
-// opcode 0 is not valid
-// opcodes 1 and 2 have length four (see day 2)
-// opcodes 3 and 4 have length two (see day 5 part 1)
-// opcodes 5 and 6 have length 3 (see day 5 part 2)
-// opcodes 7 and 8 have length 4 (see day 5 part 2)
-const LENGTHS: [usize; 9] = [0, 4, 4, 2, 2, 3, 3, 4, 4];
-
-#[derive(Eq, PartialEq, Debug)]
-struct Operation {
+mod ascii;
+mod asm;
+mod cache;
+mod cfg;
+mod compile;
+mod debugger;
+mod disasm;
+mod error;
+mod history;
+mod instruction;
+mod io;
+mod memory;
+mod network;
+mod profile;
+mod recording;
+mod snapshot;
+mod symbolic;
+mod topology;
+mod trace;
+mod watchdog;
+mod word;
+pub use ascii::{AsciiStdin, AsciiStdout};
+pub use asm::{assemble, AsmError};
+use cache::DecodeCache;
+pub use cfg::{Block, Cfg, Edge};
+pub use compile::CompileError;
+pub use debugger::{Debugger, Stop};
+pub use error::IntcodeError;
+use history::{Change, History};
+use instruction::{Instruction, Mode};
+pub use io::{InputFn, InputSource, IterInput, OutputFn, OutputSink, StdinInput, StdoutOutput};
+use memory::Memory;
+pub use network::{Control, Monitor, Network, Packet};
+pub use profile::Profile;
+pub use recording::{Recording, RecordingError, ReplayStop};
+pub use snapshot::SnapshotError;
+pub use symbolic::{Expr, Polynomial, Symbolic, SymbolicError};
+pub use topology::{Blocked, Outcome, Topology};
+pub use trace::{Trace, TraceEntry};
+use watchdog::LoopDetector;
+pub use word::Word;
+
+/// A decoded instruction, with each operand resolved to the memory location it refers to
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Operation {
     opcode: usize,
-    operand_locations: Vec<usize>,
+    operand_count: usize,
+    operand_locations: [usize; 3],
+}
+
+impl Operation {
+    /// The opcode, with the parameter modes stripped off
+    pub fn opcode(&self) -> usize {
+        self.opcode
+    }
+
+    /// The memory locations of the operands. Immediate mode operands resolve to
+    /// the location of the parameter itself.
+    pub fn operand_locations(&self) -> &[usize] {
+        &self.operand_locations[..self.operand_count]
+    }
 }
 
-#[derive(Eq, PartialEq)]
-pub struct Intcode {
-    memory: Vec<isize>,
+/// The reason an Intcode machine stopped executing
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RunState<W = isize> {
+    /// The machine reached a halt instruction
+    Halted,
+    /// The machine needs input that has not been provided yet
+    NeedsInput,
+    /// The machine produced an output value
+    Output(W),
+}
+
+/// An Intcode machine whose memory cells hold values of type `W`. The other word
+/// types are for programs that need wider values. Running, stepping, history,
+/// tracing and symbolic execution work with any word type, but the rest of the
+/// tooling is only implemented for the default of `isize`: the I/O sources and
+/// sinks, ASCII helpers, disassembler, compiler, debugger, `Topology` and
+/// `Network`.
+///
+/// Cloning is cheap: memory is shared between clones page by page until one of
+/// them writes to it, so a machine can be forked at every branch of a search.
+#[derive(Eq, PartialEq, Clone)]
+pub struct Intcode<W: Word = isize> {
+    memory: Memory<W>,
     pointer: usize,
-    input: VecDeque<isize>,
-    output: VecDeque<isize>,
+    relative_base: isize,
+    input: VecDeque<W>,
+    output: VecDeque<W>,
+    steps: u64,
+    checked: bool,
+    step_limit: Option<u64>,
+    loops: Option<LoopDetector<W>>,
+    trace: Option<Trace<W>>,
+    profile: Option<Profile>,
+    history: Option<History<W>>,
+    recording: Option<Recording<W>>,
+    decoded: DecodeCache,
 }
 
-impl Intcode {
+impl<W: Word> Intcode<W> {
     /// Create an new Intcode instance having executed one step
-    #[allow(dead_code)]
-    pub fn step(&self) -> Self {
-        unimplemented!()
+    pub fn step(&self) -> Result<Self, IntcodeError> {
+        let mut next = self.clone();
+        next.step_mut()?;
+        Ok(next)
     }
 
-    /// Execute this Intcode instance until it halts
-    pub fn execute(&mut self) {
-        let mut jumped = false;
+    /// Execute exactly one instruction in place, and return the operation that was executed.
+    /// Stepping a halted machine leaves it unchanged.
+    pub fn step_mut(&mut self) -> Result<Operation, IntcodeError> {
+        let operation = self.fetch()?;
+
+        if operation.opcode == 3 && self.input.is_empty() {
+            return Err(IntcodeError::InputExhausted {
+                pointer: self.pointer,
+            });
+        }
 
-        // Loop until halt instruction
-        while self.memory[self.pointer] != 99 {
-            let operation = self.parse_operation();
-            // println!("\n\npointer: {:?}", self.pointer);
-            // println!("operation: {:?}", operation);
-            // println!("prestate: {:?}", self.memory);
-
-            if operation.opcode == 1 {
-                // Add instruction
-                let op0 = self.memory[operation.operand_locations[0]];
-                let op1 = self.memory[operation.operand_locations[1]];
-                self.memory[operation.operand_locations[2]] = op0 + op1;
-            } else if operation.opcode == 2 {
-                // Multiply instruction
-                let op0 = self.memory[operation.operand_locations[0]];
-                let op1 = self.memory[operation.operand_locations[1]];
-                self.memory[operation.operand_locations[2]] = op0 * op1;
-            } else if operation.opcode == 3 {
-                // Input instruction
-                let input_value = self.input.pop_front().unwrap();
-                self.memory[operation.operand_locations[0]] = input_value;
-            } else if operation.opcode == 4 {
-                // Output instruction
-                let output_value = self.memory[operation.operand_locations[0]];
+        if operation.opcode != 99 {
+            if let Some(output_value) = self.execute_operation(&operation)? {
                 self.output.push_back(output_value);
-            } else if operation.opcode == 5 {
-                // Jump if true
-                let op0 = self.memory[operation.operand_locations[0]];
-                let op1 = self.memory[operation.operand_locations[1]];
-                if op0 != 0 {
-                    self.pointer = op1 as usize;
-                    jumped = true;
-                }
-            } else if operation.opcode == 6 {
-                // Jump if false
-                let op0 = self.memory[operation.operand_locations[0]];
-                let op1 = self.memory[operation.operand_locations[1]];
-                if op0 == 0 {
-                    self.pointer = op1 as usize;
-                    jumped = true;
+            }
+        }
+
+        Ok(operation)
+    }
+
+    /// Decode the operation at the current pointer without executing it
+    pub fn current_operation(&self) -> Result<Operation, IntcodeError> {
+        self.parse_operation()
+    }
+
+    /// Execute this Intcode instance until it halts, or until it needs input
+    /// that has not been provided yet. Any output is collected on the output tape.
+    /// A machine that stopped for input can be resumed by pushing more input
+    /// and calling `execute` again.
+    pub fn execute(&mut self) -> Result<RunState<W>, IntcodeError> {
+        self.run_until(false)
+    }
+
+    /// Execute this Intcode instance until it produces a single output value, halts,
+    /// or needs input that has not been provided yet. The output value is returned
+    /// directly rather than collected on the output tape.
+    pub fn run_until_output(&mut self) -> Result<RunState<W>, IntcodeError> {
+        self.run_until(true)
+    }
+
+    /// Add a value to the end of the input queue
+    pub fn push_input(&mut self, value: W) {
+        self.forget_states();
+        self.input.push_back(value);
+    }
+
+    /// Whether the machine is sitting on a halt instruction
+    pub fn is_halted(&self) -> bool {
+        matches!(
+            Instruction::decode(&self.memory, self.pointer),
+            Ok(instruction) if instruction.opcode() == 99
+        )
+    }
+
+    /// Run the machine until it halts, blocks on input, or (optionally) outputs
+    fn run_until(&mut self, pause_on_output: bool) -> Result<RunState<W>, IntcodeError> {
+        loop {
+            let operation = self.fetch()?;
+            if operation.opcode == 99 {
+                return Ok(RunState::Halted);
+            }
+
+            // Leave the pointer on the input instruction so it is retried when resumed
+            if operation.opcode == 3 && self.input.is_empty() {
+                return Ok(RunState::NeedsInput);
+            }
+
+            if let Some(output_value) = self.execute_operation(&operation)? {
+                if pause_on_output {
+                    return Ok(RunState::Output(output_value));
                 }
-            } else if operation.opcode == 7 {
-                // Less than
-                let op0 = self.memory[operation.operand_locations[0]];
-                let op1 = self.memory[operation.operand_locations[1]];
-                self.memory[operation.operand_locations[2]] = if op0 < op1 { 1 } else { 0 };
-            } else if operation.opcode == 8 {
-                // Equals
-                let op0 = self.memory[operation.operand_locations[0]];
-                let op1 = self.memory[operation.operand_locations[1]];
-                self.memory[operation.operand_locations[2]] = if op0 == op1 { 1 } else { 0 };
-            } else {
-                panic!("Invalid opcode: {}", operation.opcode)
-            };
+                self.output.push_back(output_value);
+            }
+        }
+    }
+
+    /// Execute an already-parsed operation, counting the step and recording it in
+    /// the trace, profile, history and input recording if they are enabled. Fails if the step limit has been
+    /// reached, or if loop detection sees the machine go round forever.
+    /// Returns the output value if the operation was an output instruction.
+    fn execute_operation(&mut self, operation: &Operation) -> Result<Option<W>, IntcodeError> {
+        self.check_step_limit()?;
+        let pointer = self.pointer;
+        let entry = self
+            .trace
+            .as_ref()
+            .map(|_| TraceEntry::before(self, operation));
+        let change = self
+            .history
+            .as_ref()
+            .map(|_| Change::before(self, operation));
+        let consumed = match (&self.recording, operation.opcode) {
+            (Some(_), 3) => self.input.front().cloned(),
+            _ => None,
+        };
+        let raw_opcode = self
+            .profile
+            .as_ref()
+            .and_then(|_| self.memory.read(pointer).to_isize());
 
-            // Adjust the pointer unless a jump instruction occurred
-            if !jumped {
-                self.pointer += LENGTHS[operation.opcode];
+        let output = self.apply(operation)?;
+
+        if let Some(entry) = entry {
+            let entry = entry.after(self, operation, output.clone());
+            if let Some(trace) = self.trace.as_mut() {
+                trace.push(entry);
             }
-            jumped = false;
         }
+        if let (Some(profile), Some(raw_opcode)) = (self.profile.as_mut(), raw_opcode) {
+            profile.record(pointer, raw_opcode, operation, self.pointer);
+        }
+        if let (Some(history), Some(change)) = (self.history.as_mut(), change) {
+            history.push(change);
+        }
+        if let (Some(recording), Some(value)) = (self.recording.as_mut(), consumed) {
+            recording.push(self.steps, value);
+        }
+        self.steps += 1;
+        self.check_loop(pointer, operation)?;
+
+        Ok(output)
     }
 
-    /// Create a new Intcode instance from the given string, and input.
-    pub fn new_with_input(s: &str, input: &VecDeque<isize>) -> Self {
-        let mut ic = Self::new(s);
+    /// Apply an already-parsed operation to the machine, advancing the pointer.
+    /// Returns the output value if the operation was an output instruction.
+    fn apply(&mut self, operation: &Operation) -> Result<Option<W>, IntcodeError> {
+        let mut jumped = false;
+        let mut output = None;
+        let zero = W::from_isize(0);
+        let overflow = IntcodeError::Overflow {
+            pointer: self.pointer,
+        };
 
-        ic.input = input.clone();
-        ic
+        if operation.opcode == 1 {
+            // Add instruction
+            let op0 = self.memory.read(operation.operand_locations[0]);
+            let op1 = self.memory.read(operation.operand_locations[1]);
+            let sum = if self.checked {
+                op0.checked_add(&op1).ok_or(overflow)?
+            } else {
+                op0.wrapping_add(&op1)
+            };
+            self.write(operation.operand_locations[2], sum);
+        } else if operation.opcode == 2 {
+            // Multiply instruction
+            let op0 = self.memory.read(operation.operand_locations[0]);
+            let op1 = self.memory.read(operation.operand_locations[1]);
+            let product = if self.checked {
+                op0.checked_mul(&op1).ok_or(overflow)?
+            } else {
+                op0.wrapping_mul(&op1)
+            };
+            self.write(operation.operand_locations[2], product);
+        } else if operation.opcode == 3 {
+            // Input instruction
+            let input_value = self.input.pop_front().ok_or(IntcodeError::InputExhausted {
+                pointer: self.pointer,
+            })?;
+            self.write(operation.operand_locations[0], input_value);
+        } else if operation.opcode == 4 {
+            // Output instruction
+            output = Some(self.memory.read(operation.operand_locations[0]));
+        } else if operation.opcode == 5 {
+            // Jump if true
+            let op0 = self.memory.read(operation.operand_locations[0]);
+            let op1 = self.memory.read(operation.operand_locations[1]);
+            if op0 != zero {
+                self.pointer = self.jump_target(op1)?;
+                jumped = true;
+            }
+        } else if operation.opcode == 6 {
+            // Jump if false
+            let op0 = self.memory.read(operation.operand_locations[0]);
+            let op1 = self.memory.read(operation.operand_locations[1]);
+            if op0 == zero {
+                self.pointer = self.jump_target(op1)?;
+                jumped = true;
+            }
+        } else if operation.opcode == 7 {
+            // Less than
+            let op0 = self.memory.read(operation.operand_locations[0]);
+            let op1 = self.memory.read(operation.operand_locations[1]);
+            self.write(
+                operation.operand_locations[2],
+                W::from_isize(if op0 < op1 { 1 } else { 0 }),
+            );
+        } else if operation.opcode == 8 {
+            // Equals
+            let op0 = self.memory.read(operation.operand_locations[0]);
+            let op1 = self.memory.read(operation.operand_locations[1]);
+            self.write(
+                operation.operand_locations[2],
+                W::from_isize(if op0 == op1 { 1 } else { 0 }),
+            );
+        } else if operation.opcode == 9 {
+            // Adjust relative base
+            let op0 = self.memory.read(operation.operand_locations[0]);
+            self.relative_base = op0
+                .to_isize()
+                .and_then(|op0| self.relative_base.checked_add(op0))
+                .ok_or(overflow)?;
+        } else {
+            return Err(IntcodeError::InvalidOpcode {
+                pointer: self.pointer,
+                opcode: operation.opcode as isize,
+            });
+        };
+
+        // Adjust the pointer unless a jump instruction occurred
+        if !jumped {
+            self.pointer += instruction::length(operation.opcode);
+        }
+
+        Ok(output)
     }
 
-    /// Create a new Intcode instance directly from the given string
-    pub fn new(s: &str) -> Self {
-        let memory: Vec<_> = s
+    /// Check that a jump target is a valid address
+    fn jump_target(&self, target: W) -> Result<usize, IntcodeError> {
+        let target = target.to_isize().ok_or(IntcodeError::Overflow {
+            pointer: self.pointer,
+        })?;
+        if target < 0 {
+            return Err(IntcodeError::NegativeAddress {
+                pointer: self.pointer,
+                address: target,
+            });
+        }
+        Ok(target as usize)
+    }
+
+    /// Create a new Intcode instance from the given string, with any word type.
+    /// Machines with the default word type can use `new` instead.
+    pub fn parse(s: &str) -> Result<Self, IntcodeError> {
+        let memory = s
             .trim_end()
-            .split(",")
-            .map(|s| s.parse().unwrap())
-            .collect();
+            .split(',')
+            .enumerate()
+            .map(|(offset, value)| {
+                value.trim().parse().map_err(|_| IntcodeError::Parse {
+                    offset,
+                    value: value.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self::from_memory(memory.into()))
+    }
 
+    /// A machine at the start of the given memory, with nothing enabled
+    fn from_memory(memory: Memory<W>) -> Self {
         Self {
             memory,
             pointer: 0,
+            relative_base: 0,
             input: VecDeque::new(),
             output: VecDeque::new(),
+            steps: 0,
+            checked: true,
+            step_limit: None,
+            loops: None,
+            trace: None,
+            profile: None,
+            history: None,
+            recording: None,
+            decoded: DecodeCache::default(),
         }
     }
 
+    /// Turn checked arithmetic on or off. It is on by default, so an addition or
+    /// multiplication that overflows the word type stops the machine with an
+    /// error. Turning it off makes results wrap around instead.
+    pub fn set_checked(&mut self, checked: bool) {
+        self.checked = checked;
+    }
+
     /// Read an element of memory given an address
-    pub fn read(&self, address: usize) -> isize {
-        self.memory[address]
+    pub fn read(&self, address: usize) -> W {
+        self.memory.read(address)
+    }
+
+    /// The address of the next instruction to execute
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// The base address for relative mode parameters
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    /// The number of instructions this machine has executed
+    pub fn steps(&self) -> u64 {
+        self.steps
     }
 
     /// Get the output tape from the machine
-    pub fn get_output(&self) -> VecDeque<isize> {
+    pub fn get_output(&self) -> VecDeque<W> {
         self.output.clone()
     }
 
+    /// Borrow the output tape from the machine
+    pub fn output(&self) -> &VecDeque<W> {
+        &self.output
+    }
+
+    /// Take everything from the output tape, leaving it empty
+    pub fn take_output(&mut self) -> VecDeque<W> {
+        std::mem::take(&mut self.output)
+    }
+
     /// Mutates the given memory in the memory tape to the given value
     /// Used specifically for the weird input technique in day 2
-    pub fn mutate_memory(&mut self, location: usize, value: isize) {
-        self.memory[location] = value;
+    pub fn mutate_memory(&mut self, location: usize, value: W) {
+        self.forget_states();
+        self.write(location, value);
+    }
+
+    /// Turn the decoded instruction cache on or off. It is on by default, and is
+    /// only worth turning off to compare against uncached execution.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decoded.set_enabled(enabled);
+    }
+
+    /// Turn the sparse map that keeps writes to huge addresses from allocating
+    /// everything below them on or off. It is on by default. With it off, memory
+    /// is one contiguous tape however far it has to grow.
+    pub fn set_sparse_memory(&mut self, enabled: bool) {
+        self.memory.set_sparse(enabled);
+    }
+
+    /// Write to memory, dropping any cached instruction decoded from that address
+    fn write(&mut self, address: usize, value: W) {
+        self.memory.write(address, value);
+        self.decoded.invalidate(address);
     }
 
     /// Render memory as a string
@@ -143,41 +452,71 @@ impl Intcode {
 
         for value in self.memory.iter() {
             s.push_str(&format!("{}", value));
-            s.push_str(",");
+            s.push(',');
         }
 
         s.pop();
         s
     }
 
-    /// arses the operation at the current pointer location
-    /// Panics if the value at that cell is not a valid operation
-    fn parse_operation(&self) -> Operation {
-        let op_digits = self.memory[self.pointer] as usize;
-        let opcode: usize = op_digits % 100;
-        let mut modes_digits: usize = op_digits / 100;
+    /// Parses the operation at the current pointer location
+    fn parse_operation(&self) -> Result<Operation, IntcodeError> {
+        let instruction = Instruction::decode(&self.memory, self.pointer)?;
+        self.resolve(&instruction)
+    }
 
-        // Expected number of operands for this opcode. Knowing this value is
-        // necessary because leading zeros may be omitted
-        let num_operands = LENGTHS[opcode];
+    /// Parses the operation at the current pointer location, going through the
+    /// decoded instruction cache
+    fn fetch(&mut self) -> Result<Operation, IntcodeError> {
+        let instruction = self.decoded.decode(&self.memory, self.pointer)?;
+        self.resolve(&instruction)
+    }
 
-        // Loop through looking up the operands
-        let mut operand_locations: Vec<usize> = Vec::new();
-        for offset in 1..num_operands {
-            if modes_digits % 10 == 1 {
-                // Immediate
-                operand_locations.push(self.pointer + offset);
-            } else {
-                // Position
-                operand_locations.push(self.memory[self.pointer + offset] as usize);
+    /// Look up the memory location each parameter of an instruction refers to
+    fn resolve(&self, instruction: &Instruction) -> Result<Operation, IntcodeError> {
+        let mut operation = Operation {
+            opcode: instruction.opcode(),
+            operand_count: 0,
+            operand_locations: [0; 3],
+        };
+        for (i, (mode, parameter)) in instruction.parameters().enumerate() {
+            let address = match mode {
+                Mode::Position => parameter,
+                Mode::Immediate => (self.pointer + i + 1) as isize,
+                Mode::Relative => {
+                    self.relative_base
+                        .checked_add(parameter)
+                        .ok_or(IntcodeError::Overflow {
+                            pointer: self.pointer,
+                        })?
+                }
+            };
+            if address < 0 {
+                return Err(IntcodeError::NegativeAddress {
+                    pointer: self.pointer,
+                    address,
+                });
             }
-            modes_digits /= 10;
+            operation.operand_locations[i] = address as usize;
+            operation.operand_count += 1;
         }
 
-        Operation {
-            opcode,
-            operand_locations,
-        }
+        Ok(operation)
+    }
+}
+
+impl Intcode {
+    /// Create a new Intcode instance from the given string, and input.
+    pub fn new_with_input(s: &str, input: &VecDeque<isize>) -> Result<Self, IntcodeError> {
+        let mut ic = Self::new(s)?;
+
+        ic.input = input.clone();
+        Ok(ic)
+    }
+
+    /// Create a new Intcode instance directly from the given string
+    pub fn new(s: &str) -> Result<Self, IntcodeError> {
+        Self::parse(s)
     }
 }
 
@@ -189,10 +528,379 @@ mod tests {
     fn direct_io_1() {
         let mut input = VecDeque::new();
         input.push_back(1);
-        let mut machine = Intcode::new_with_input(&"3,0,4,0,99", input);
-        machine.execute();
+        let mut machine = Intcode::new_with_input("3,0,4,0,99", &input).unwrap();
+        machine.execute().unwrap();
         let mut expected = VecDeque::new();
         expected.push_back(1);
         assert_eq!(machine.get_output(), expected);
     }
+
+    #[test]
+    fn pause_for_input() {
+        // Echo two inputs back, doubled
+        let mut machine = Intcode::new("3,0,1002,0,2,0,4,0,3,0,1002,0,2,0,4,0,99").unwrap();
+        assert_eq!(machine.execute().unwrap(), RunState::NeedsInput);
+
+        machine.push_input(3);
+        assert_eq!(machine.execute().unwrap(), RunState::NeedsInput);
+        assert_eq!(
+            machine.get_output(),
+            vec![6].into_iter().collect::<VecDeque<_>>()
+        );
+
+        machine.push_input(5);
+        assert_eq!(machine.execute().unwrap(), RunState::Halted);
+        assert_eq!(
+            machine.get_output(),
+            vec![6, 10].into_iter().collect::<VecDeque<_>>()
+        );
+        assert!(machine.is_halted());
+        assert_eq!(machine.output().back(), Some(&10));
+    }
+
+    #[test]
+    fn halted_with_mode_digits() {
+        // 199 decodes as a halt, just like 99
+        let mut machine = Intcode::new("199").unwrap();
+        assert!(machine.is_halted());
+        assert_eq!(machine.execute().unwrap(), RunState::Halted);
+        assert!(!Intcode::new("1,0,0,0,99").unwrap().is_halted());
+    }
+
+    #[test]
+    fn run_until_output() {
+        let mut machine = Intcode::new("104,1,104,2,3,0,99").unwrap();
+        assert_eq!(machine.run_until_output().unwrap(), RunState::Output(1));
+        assert_eq!(machine.run_until_output().unwrap(), RunState::Output(2));
+        assert_eq!(machine.run_until_output().unwrap(), RunState::NeedsInput);
+        machine.push_input(0);
+        assert_eq!(machine.run_until_output().unwrap(), RunState::Halted);
+        assert!(machine.get_output().is_empty());
+    }
+
+    #[test]
+    fn step_is_pure() {
+        let machine = Intcode::new("1,0,0,0,99").unwrap();
+        let next = machine.step().unwrap();
+        assert_eq!(machine.memory_string(), "1,0,0,0,99");
+        assert_eq!(next.memory_string(), "2,0,0,0,99");
+        assert!(next.is_halted());
+    }
+
+    #[test]
+    fn step_mut_returns_operation() {
+        let mut machine = Intcode::new("1002,4,3,4,33").unwrap();
+        let operation = machine.step_mut().unwrap();
+        assert_eq!(operation.opcode(), 2);
+        assert_eq!(operation.operand_locations(), &[4, 2, 4]);
+        assert_eq!(machine.read(4), 99);
+
+        // Stepping a halted machine does nothing
+        let operation = machine.step_mut().unwrap();
+        assert_eq!(operation.opcode(), 99);
+        assert_eq!(machine.memory_string(), "1002,4,3,4,99");
+    }
+
+    #[test]
+    fn parse_error() {
+        assert_eq!(
+            Intcode::new("1,0,x,0,99").err(),
+            Some(IntcodeError::Parse {
+                offset: 2,
+                value: "x".into()
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let mut machine = Intcode::new("1101,1,1,0,42").unwrap();
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::InvalidOpcode {
+                pointer: 4,
+                opcode: 42
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_mode() {
+        let mut machine = Intcode::new("301,0,0,0,99").unwrap();
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::InvalidMode {
+                pointer: 0,
+                mode: 3
+            })
+        );
+    }
+
+    #[test]
+    fn input_exhausted() {
+        let mut machine = Intcode::new("3,0,99").unwrap();
+        assert_eq!(
+            machine.step_mut(),
+            Err(IntcodeError::InputExhausted { pointer: 0 })
+        );
+    }
+
+    #[test]
+    fn negative_address() {
+        let mut machine = Intcode::new("4,-1,99").unwrap();
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::NegativeAddress {
+                pointer: 0,
+                address: -1
+            })
+        );
+    }
+
+    #[test]
+    fn write_to_immediate() {
+        let mut machine = Intcode::new("11101,1,1,0,99").unwrap();
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::WriteToImmediate { pointer: 0 })
+        );
+    }
+
+    #[test]
+    fn take_output() {
+        let mut machine = Intcode::new("104,1,104,2,99").unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.take_output(), vec![1, 2]);
+        assert!(machine.get_output().is_empty());
+    }
+
+    #[test]
+    fn day9_quine() {
+        // The program keeps its counters at addresses 100 and 101, past the end of the tape
+        let program = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        let mut machine = Intcode::new(program).unwrap();
+        machine.execute().unwrap();
+        let expected: VecDeque<isize> = program.split(',').map(|s| s.parse().unwrap()).collect();
+        assert_eq!(machine.get_output(), expected);
+    }
+
+    #[test]
+    fn day9_sixteen_digit_output() {
+        let mut machine = Intcode::new("1102,34915192,34915192,7,4,7,99,0").unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output()[0].to_string().len(), 16);
+    }
+
+    #[test]
+    fn day9_large_number() {
+        let mut machine = Intcode::new("104,1125899906842624,99").unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output()[0], 1125899906842624);
+    }
+
+    #[test]
+    fn write_past_end_of_tape() {
+        let mut machine = Intcode::new("1101,2,3,10,99").unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.read(10), 5);
+        assert_eq!(machine.read(11), 0);
+        assert_eq!(machine.memory_string(), "1101,2,3,10,99,0,0,0,0,0,5");
+    }
+
+    #[test]
+    fn relative_mode_input() {
+        let mut input = VecDeque::new();
+        input.push_back(7);
+        let mut machine = Intcode::new_with_input("109,10,203,-3,204,-3,99,0", &input).unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.read(7), 7);
+        assert_eq!(machine.get_output()[0], 7);
+    }
+
+    #[test]
+    fn rewriting_cached_instruction() {
+        // IN c / loop: ADD c, #-1, c / JNZ c, #loop / OUT c / HLT / c: data 0
+        let mut machine = Intcode::new("3,12,1001,12,-1,12,1005,12,2,4,12,99,0").unwrap();
+        machine.push_input(1000);
+        for _ in 0..11 {
+            machine.step_mut().unwrap();
+        }
+        assert_eq!(machine.pointer(), 2);
+
+        // Turn the cached ADD into MUL c, #0, c so the loop ends on the next pass
+        machine.mutate_memory(2, 1002);
+        machine.mutate_memory(4, 0);
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output(), vec![0]);
+        assert_eq!(machine.steps(), 14);
+    }
+
+    // MUL #a, #2, c / OUT c / HLT / c: data 0
+    fn doubling(a: &str) -> String {
+        format!("1102,{},2,7,4,7,99,0", a)
+    }
+
+    #[test]
+    fn wide_words() {
+        let mut machine = Intcode::<i128>::parse(&doubling("9223372036854775807")).unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output(), vec![18446744073709551614]);
+
+        let mut machine = Intcode::<i64>::parse(&doubling("9223372036854775807")).unwrap();
+        machine.set_checked(false);
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output(), vec![-2]);
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn big_words() {
+        use num_bigint::BigInt;
+
+        let max = i128::MAX.to_string();
+        let mut machine = Intcode::<BigInt>::parse(&doubling(&max)).unwrap();
+        machine.execute().unwrap();
+        let expected: BigInt = "340282366920938463463374607431768211454".parse().unwrap();
+        assert_eq!(machine.get_output(), vec![expected]);
+
+        // A value too large to be an address can't be used as one
+        let mut machine = Intcode::<BigInt>::parse(&format!("1,{}0,0,0,99", max)).unwrap();
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::Overflow { pointer: 0 })
+        );
+    }
+
+    #[test]
+    fn checked_overflow() {
+        let mut machine = Intcode::<i64>::parse(&doubling("9223372036854775807")).unwrap();
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::Overflow { pointer: 0 })
+        );
+
+        // ADD #1, #-1, c / ADD c, #max, c / ADD c, #1, c / HLT / c: data 0
+        let program = "1101,1,-1,13,1001,13,9223372036854775807,13,1001,13,1,13,99,0";
+        let mut machine = Intcode::<i64>::parse(program).unwrap();
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::Overflow { pointer: 8 })
+        );
+        assert_eq!(machine.read(13), i64::MAX);
+    }
+
+    // A droid in a maze, driven like the day 15 repair droid. Each input is a
+    // direction (1 north, 2 south, 3 west, 4 east), and each output says whether
+    // the droid hit a wall (0), moved (1), or moved onto the oxygen system (2).
+    const DROID: &str = "
+        loop:   IN   dir
+                ADD  x, #0, nx
+                ADD  y, #0, ny
+                EQ   dir, #1, t
+                JZ   t, #not_n
+                ADD  ny, #-1, ny
+        not_n:  EQ   dir, #2, t
+                JZ   t, #not_s
+                ADD  ny, #1, ny
+        not_s:  EQ   dir, #3, t
+                JZ   t, #not_w
+                ADD  nx, #-1, nx
+        not_w:  EQ   dir, #4, t
+                JZ   t, #not_e
+                ADD  nx, #1, nx
+        not_e:  MUL  ny, #SIZE, cell
+                ADD  cell, nx, cell
+                ADD  cell, #grid, cell
+                ARB  cell
+                ADD  @0, #0, status
+                MUL  cell, #-1, cell
+                ARB  cell
+                OUT  status
+                JZ   status, #loop
+                ADD  nx, #0, x
+                ADD  ny, #0, y
+                JZ   #0, #loop
+        dir:    data 0
+        x:      data 1
+        y:      data 1
+        nx:     data 0
+        ny:     data 0
+        t:      data 0
+        cell:   data 0
+        status: data 0
+        grid:   data GRID
+    ";
+
+    /// The droid in a square maze of pillars, starting in the top left corner
+    /// with the oxygen system in the bottom right
+    fn droid(size: usize) -> Intcode {
+        let grid = (0..size * size)
+            .map(|i| {
+                let (x, y) = (i % size, i / size);
+                let edge = x == 0 || y == 0 || x == size - 1 || y == size - 1;
+                let pillar = x % 2 == 0 && y % 2 == 0;
+                if x == size - 2 && y == size - 2 {
+                    "2"
+                } else if edge || pillar {
+                    "0"
+                } else {
+                    "1"
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let source = DROID
+            .replace("SIZE", &size.to_string())
+            .replace("GRID", &grid);
+        Intcode::new(&assemble(&source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn fork_explorer() {
+        // Breadth first search, forking the droid to try every direction from
+        // every square it reaches
+        let start = droid(61);
+        let mut seen = std::collections::HashSet::new();
+        seen.insert((1, 1));
+        let mut frontier = vec![(start.clone(), (1, 1))];
+        let mut oxygen = None;
+        let mut forks = 0;
+        let mut distance = 0;
+        while !frontier.is_empty() {
+            distance += 1;
+            let mut next = Vec::new();
+            for (machine, (x, y)) in frontier {
+                let moves = [
+                    (1, (x, y - 1)),
+                    (2, (x, y + 1)),
+                    (3, (x - 1, y)),
+                    (4, (x + 1, y)),
+                ];
+                for (direction, position) in moves.iter().copied() {
+                    if !seen.insert(position) {
+                        continue;
+                    }
+                    let mut fork = machine.clone();
+                    forks += 1;
+                    fork.push_input(direction);
+                    match fork.run_until_output().unwrap() {
+                        RunState::Output(0) => {}
+                        RunState::Output(1) => next.push((fork, position)),
+                        RunState::Output(2) => oxygen = Some(distance),
+                        state => panic!("unexpected {:?}", state),
+                    }
+                }
+            }
+
+            // Only the page holding the droid's variables has been copied
+            for (machine, _) in &next {
+                let pages = start.memory.page_count();
+                assert_eq!(machine.memory.shared_pages(&start.memory), pages - 1);
+            }
+            frontier = next;
+        }
+
+        assert_eq!(oxygen, Some(116));
+        assert!(forks > 2500, "only forked {} times", forks);
+    }
 }
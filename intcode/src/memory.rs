@@ -0,0 +1,312 @@
+use crate::word::Word;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+// Addresses below this limit are stored contiguously, growing the tape as needed.
+// Anything above it goes in a sparse map so that a single write to some huge
+// address doesn't allocate gigabytes of zeros, unless the sparse map is turned off.
+const DENSE_LIMIT: usize = 1 << 20;
+
+// The contiguous part of memory is split into pages of this many cells. Clones
+// share their pages, and a page is only copied when one of them writes to it, so
+// forking a machine costs one copy per page it goes on to touch.
+const PAGE_SIZE: usize = 256;
+
+/// The memory tape of an Intcode machine. Every address is implicitly zero
+/// until it is written. Two memories are equal when every address reads the
+/// same in both, however their cells happen to be stored.
+#[derive(Debug, Clone)]
+pub struct Memory<W = isize> {
+    // Addresses from here up are kept in `sparse`
+    dense_limit: usize,
+    len: usize,
+    // Cells past `len` on the last page are always zero
+    pages: Vec<Arc<[W; PAGE_SIZE]>>,
+    sparse: Arc<HashMap<usize, W>>,
+}
+
+impl<W: Word> Memory<W> {
+    /// Read the value at the given address. Addresses that have never been
+    /// written read as zero.
+    pub fn read(&self, address: usize) -> W {
+        if address < self.len {
+            return self.pages[address / PAGE_SIZE][address % PAGE_SIZE].clone();
+        }
+        match self.sparse.get(&address) {
+            Some(value) => value.clone(),
+            None => W::from_isize(0),
+        }
+    }
+
+    /// Write the value to the given address, growing memory if necessary.
+    pub fn write(&mut self, address: usize, value: W) {
+        if address < self.len.max(self.dense_limit) {
+            if address >= self.len {
+                self.grow(address + 1);
+            }
+            let page = Arc::make_mut(&mut self.pages[address / PAGE_SIZE]);
+            page[address % PAGE_SIZE] = value;
+        } else {
+            Arc::make_mut(&mut self.sparse).insert(address, value);
+        }
+    }
+
+    /// Turn the sparse map for huge addresses on or off. It is on by default.
+    /// With it off, every write goes in the contiguous part of memory, however
+    /// far that has to grow, and cells already in the map are moved there.
+    pub fn set_sparse(&mut self, enabled: bool) {
+        if enabled {
+            self.dense_limit = DENSE_LIMIT;
+            return;
+        }
+        self.dense_limit = usize::MAX;
+        let cells = std::mem::take(Arc::make_mut(&mut self.sparse));
+        for (address, value) in cells {
+            self.write(address, value);
+        }
+    }
+
+    /// Extend the contiguous part of memory to the given length with zeros. New
+    /// pages all share a single zeroed page until they are written.
+    fn grow(&mut self, len: usize) {
+        let pages = len.div_ceil(PAGE_SIZE);
+        if pages > self.pages.len() {
+            let zeros = Arc::new(zeroed());
+            self.pages.resize(pages, zeros);
+        }
+        self.len = len;
+    }
+
+    /// Shrink the contiguous part of memory back to the given length, as if it
+    /// had never grown past it
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.pages.truncate(len.div_ceil(PAGE_SIZE));
+        let start = len % PAGE_SIZE;
+        if start != 0 {
+            let end = PAGE_SIZE.min(self.len - (len - start));
+            if let Some(last) = self.pages.last_mut() {
+                let page = Arc::make_mut(last);
+                for cell in &mut page[start..end] {
+                    *cell = W::from_isize(0);
+                }
+            }
+        }
+        self.len = len;
+    }
+
+    /// Forget a cell written above the contiguous part of memory, so that it
+    /// reads as zero again
+    pub(crate) fn remove(&mut self, address: usize) {
+        if self.sparse.contains_key(&address) {
+            Arc::make_mut(&mut self.sparse).remove(&address);
+        }
+    }
+
+    /// Whether the given address has a cell, either in the contiguous part of
+    /// memory or written above it
+    pub(crate) fn contains(&self, address: usize) -> bool {
+        address < self.len || self.sparse.contains_key(&address)
+    }
+
+    /// The length of the contiguous part of memory. That is the loaded program
+    /// plus anything it has grown into since.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Iterate over the contiguous part of memory
+    pub fn iter(&self) -> impl Iterator<Item = &W> {
+        self.pages
+            .iter()
+            .flat_map(|page| page.iter())
+            .take(self.len)
+    }
+
+    /// Every cell that doesn't read as zero, in address order
+    fn nonzero_cells(&self) -> impl Iterator<Item = (usize, &W)> {
+        let zero = W::from_isize(0);
+        self.iter()
+            .enumerate()
+            .chain(self.sparse_cells())
+            .filter(move |(_, value)| **value != zero)
+    }
+}
+
+impl<W> Memory<W> {
+    /// The cells written above the contiguous part of memory, in address order
+    pub fn sparse_cells(&self) -> Vec<(usize, &W)> {
+        let mut cells = self
+            .sparse
+            .iter()
+            .map(|(address, value)| (*address, value))
+            .collect::<Vec<_>>();
+        cells.sort_by_key(|(address, _)| *address);
+        cells
+    }
+
+    /// How many pages this memory still shares with another
+    #[cfg(test)]
+    pub(crate) fn shared_pages(&self, other: &Self) -> usize {
+        self.pages
+            .iter()
+            .zip(&other.pages)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count()
+    }
+
+    /// How many pages the contiguous part of memory is split into
+    #[cfg(test)]
+    pub(crate) fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+/// A page of memory that has never been written
+fn zeroed<W: Word>() -> [W; PAGE_SIZE] {
+    std::array::from_fn(|_| W::from_isize(0))
+}
+
+impl<W: Word> PartialEq for Memory<W> {
+    fn eq(&self, other: &Self) -> bool {
+        self.nonzero_cells().eq(other.nonzero_cells())
+    }
+}
+
+impl<W: Word> Eq for Memory<W> {}
+
+impl<W: Word> Hash for Memory<W> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for cell in self.nonzero_cells() {
+            cell.hash(state);
+        }
+    }
+}
+
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    fn from(dense: Vec<W>) -> Self {
+        let len = dense.len();
+        let mut pages = Vec::with_capacity(len.div_ceil(PAGE_SIZE));
+        let mut values = dense.into_iter();
+        while pages.len() * PAGE_SIZE < len {
+            let mut page = zeroed();
+            for (cell, value) in page.iter_mut().zip(values.by_ref()) {
+                *cell = value;
+            }
+            pages.push(Arc::new(page));
+        }
+        Self {
+            dense_limit: DENSE_LIMIT,
+            len,
+            pages,
+            sparse: Arc::new(HashMap::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_past_end_is_zero() {
+        let memory = Memory::<isize>::from(vec![1, 2, 3]);
+        assert_eq!(memory.read(3), 0);
+        assert_eq!(memory.read(1_000_000_000), 0);
+    }
+
+    #[test]
+    fn write_past_end_grows() {
+        let mut memory = Memory::<isize>::from(vec![1, 2, 3]);
+        memory.write(10, 7);
+        assert_eq!(memory.iter().count(), 11);
+        assert_eq!(memory.read(10), 7);
+        assert_eq!(memory.read(5), 0);
+
+        memory.write(1000, 8);
+        assert_eq!(memory.iter().count(), 1001);
+        assert_eq!(memory.read(1000), 8);
+        assert_eq!(memory.read(999), 0);
+    }
+
+    #[test]
+    fn huge_write_is_sparse() {
+        let mut memory = Memory::<isize>::from(vec![1, 2, 3]);
+        memory.write(1_000_000_000, 7);
+        assert_eq!(memory.iter().count(), 3);
+        assert_eq!(memory.read(1_000_000_000), 7);
+    }
+
+    #[test]
+    fn sparse_map_can_be_turned_off() {
+        let mut memory = Memory::<isize>::from(vec![1, 2, 3]);
+        memory.write(2_000_000, 7);
+        memory.set_sparse(false);
+        assert!(memory.sparse_cells().is_empty());
+        assert_eq!(memory.len(), 2_000_001);
+        assert_eq!(memory.read(2_000_000), 7);
+
+        memory.write(3_000_000, 8);
+        assert_eq!(memory.len(), 3_000_001);
+        memory.set_sparse(true);
+        memory.write(4_000_000, 9);
+        assert_eq!(memory.len(), 3_000_001);
+        assert_eq!(memory.sparse_cells(), vec![(4_000_000, &9)]);
+        memory.write(2_500_000, 10);
+        assert_eq!(memory.read(2_500_000), 10);
+    }
+
+    #[test]
+    fn truncate_forgets_growth() {
+        let original = Memory::<isize>::from(vec![1, 2, 3]);
+        let mut memory = original.clone();
+        memory.write(300, 7);
+        memory.write(5, 8);
+        memory.truncate(3);
+        assert_eq!(memory, original);
+        assert_eq!(memory.read(5), 0);
+    }
+
+    #[test]
+    fn equality_ignores_layout() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let hash = |memory: &Memory| {
+            let mut hasher = DefaultHasher::new();
+            memory.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        // The same cell stored sparse and dense, with trailing zeros after it
+        let mut sparse = Memory::<isize>::from(vec![1, 2, 3]);
+        sparse.write(2_000_000, 7);
+        let mut dense = sparse.clone();
+        dense.set_sparse(false);
+        dense.set_sparse(true);
+        assert_eq!(sparse, dense);
+        assert_eq!(hash(&sparse), hash(&dense));
+
+        let mut zeros = Memory::<isize>::from(vec![1, 2, 3, 0, 0]);
+        zeros.write(1_000_000_000, 0);
+        assert_eq!(zeros, Memory::from(vec![1, 2, 3]));
+        assert_eq!(hash(&zeros), hash(&Memory::from(vec![1, 2, 3])));
+
+        assert_ne!(sparse, Memory::from(vec![1, 2, 3]));
+        assert_ne!(Memory::<isize>::from(vec![0, 1]), Memory::from(vec![1]));
+    }
+
+    #[test]
+    fn clones_share_untouched_pages() {
+        let memory = Memory::<isize>::from((0..1000).collect::<Vec<_>>());
+        let mut fork = memory.clone();
+        fork.write(300, -1);
+
+        assert_eq!(memory.shared_pages(&fork), memory.page_count() - 1);
+        assert_eq!(memory.read(300), 300);
+        assert_eq!(fork.read(300), -1);
+        assert_ne!(memory, fork);
+    }
+}
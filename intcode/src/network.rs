@@ -0,0 +1,629 @@
+use crate::{InputSource, Intcode, IntcodeError, OutputSink};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// A packet travelling between machines on the network
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Packet {
+    pub destination: usize,
+    pub x: isize,
+    pub y: isize,
+}
+
+/// What the network should do after consulting its monitor
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Control {
+    /// Carry on as normal
+    Continue,
+    /// Route the given packet, then carry on
+    Send(Packet),
+    /// Stop the network
+    Stop,
+}
+
+impl Packet {
+    /// Make a packet from the three values output by the machine at `machine`
+    fn from_output(machine: usize, values: &[isize]) -> Result<Self, IntcodeError> {
+        if values[0] < 0 {
+            return Err(IntcodeError::NegativeDestination {
+                machine,
+                destination: values[0],
+            });
+        }
+        Ok(Packet {
+            destination: values[0] as usize,
+            x: values[1],
+            y: values[2],
+        })
+    }
+}
+
+/// A device attached to the network that sees its traffic, like the NAT in day 23
+pub trait Monitor {
+    /// Called with every packet as it is routed, including those the monitor sends itself
+    fn packet(&mut self, _packet: &Packet) {}
+
+    /// Called with every packet sent to an address that has no machine on it.
+    /// By default these packets are dropped.
+    fn unrouted(&mut self, _packet: Packet) -> Control {
+        Control::Continue
+    }
+
+    /// Called when every machine has read -1 since it last received a packet, and
+    /// none of them sent anything. By default the network stops.
+    fn idle(&mut self) -> Control {
+        Control::Stop
+    }
+}
+
+/// A monitor that never intervenes
+impl Monitor for () {}
+
+/// A single machine on the network along with its incoming packets
+struct Node {
+    machine: Intcode,
+    queue: VecDeque<Packet>,
+    // Output values that don't yet make up a whole packet
+    partial: Vec<isize>,
+    // Whether the machine has read -1 since it last received a packet
+    waiting: bool,
+}
+
+/// A network of Intcode machines that send packets to each other. Each machine
+/// is given its address as its first input. After that, a machine outputs three
+/// values (destination, X, Y) to send a packet, and reads X then Y to receive one.
+/// A machine that reads from an empty queue gets -1.
+pub struct Network {
+    nodes: Vec<Node>,
+}
+
+impl Network {
+    /// Create a network of `size` copies of the given machine, with addresses
+    /// starting from zero
+    pub fn new(machine: &Intcode, size: usize) -> Self {
+        Self::from_machines(vec![machine.clone(); size])
+    }
+
+    /// Create a network of the given machines. Each machine's address is its index.
+    pub fn from_machines(machines: Vec<Intcode>) -> Self {
+        let nodes = machines
+            .into_iter()
+            .enumerate()
+            .map(|(address, mut machine)| {
+                machine.push_input(address as isize);
+                Node {
+                    machine,
+                    queue: VecDeque::new(),
+                    partial: Vec::new(),
+                    waiting: false,
+                }
+            })
+            .collect();
+
+        Self { nodes }
+    }
+
+    /// The number of machines on the network
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether there are no machines on the network
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The machine at the given address
+    pub fn machine(&self, address: usize) -> Option<&Intcode> {
+        self.nodes.get(address).map(|node| &node.machine)
+    }
+
+    /// Queue a packet for delivery to its destination. Packets for addresses with
+    /// no machine are dropped.
+    pub fn send(&mut self, packet: Packet) {
+        if let Some(node) = self.nodes.get_mut(packet.destination) {
+            node.queue.push_back(packet);
+        }
+    }
+
+    /// Run the network on this thread, giving each machine a turn in address order
+    /// so that runs are deterministic. Stops when the monitor says so, or when
+    /// every machine has halted. Fails if a machine does, or if one sends a packet
+    /// to a negative address.
+    pub fn run<M: Monitor>(&mut self, monitor: &mut M) -> Result<(), IntcodeError> {
+        loop {
+            let mut sent = Vec::new();
+
+            for (address, node) in self.nodes.iter_mut().enumerate() {
+                if node.machine.is_halted() {
+                    continue;
+                }
+
+                // Deliver queued packets, or -1 if a blocked machine has nothing to read
+                let mut empty_read = false;
+                if node.queue.is_empty() {
+                    if node.machine.input.is_empty() {
+                        node.machine.push_input(-1);
+                        empty_read = true;
+                    }
+                } else {
+                    node.waiting = false;
+                    for packet in node.queue.drain(..) {
+                        node.machine.push_input(packet.x);
+                        node.machine.push_input(packet.y);
+                    }
+                }
+
+                node.machine.execute()?;
+                if empty_read && node.machine.input.is_empty() {
+                    node.waiting = true;
+                }
+                node.partial.extend(node.machine.take_output());
+                while node.partial.len() >= 3 {
+                    let values: Vec<isize> = node.partial.drain(..3).collect();
+                    sent.push(Packet::from_output(address, &values)?);
+                }
+            }
+
+            let idle = sent.is_empty()
+                && self
+                    .nodes
+                    .iter()
+                    .all(|node| node.waiting || node.machine.is_halted());
+            for packet in sent {
+                if self.route(packet, monitor) == Control::Stop {
+                    return Ok(());
+                }
+            }
+
+            if self.nodes.iter().all(|node| node.machine.is_halted()) {
+                return Ok(());
+            }
+
+            if idle {
+                match monitor.idle() {
+                    Control::Continue => {}
+                    Control::Send(packet) => {
+                        if self.route(packet, monitor) == Control::Stop {
+                            return Ok(());
+                        }
+                    }
+                    Control::Stop => return Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Deliver a packet, consulting the monitor. Returns `Control::Stop` if the
+    /// monitor asked to stop the network.
+    fn route<M: Monitor>(&mut self, packet: Packet, monitor: &mut M) -> Control {
+        let mut next = Some(packet);
+        while let Some(packet) = next.take() {
+            monitor.packet(&packet);
+            if packet.destination < self.nodes.len() {
+                self.send(packet);
+            } else {
+                match monitor.unrouted(packet) {
+                    Control::Continue => {}
+                    Control::Send(packet) => next = Some(packet),
+                    Control::Stop => return Control::Stop,
+                }
+            }
+        }
+        Control::Continue
+    }
+
+    /// Run the network with each machine on its own thread. The monitor is called
+    /// from this thread. Stops when the monitor says so, or when every machine has
+    /// halted.
+    ///
+    /// Idle detection is only approximate in this mode: the network is considered
+    /// idle once no packets are in flight and every machine has read -1 at least
+    /// twice in a row.
+    pub fn run_threaded<M: Monitor>(self, monitor: &mut M) -> Result<(), IntcodeError> {
+        let size = self.nodes.len();
+        let (router, packets) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let idle: Arc<Vec<AtomicBool>> =
+            Arc::new((0..size).map(|_| AtomicBool::new(false)).collect());
+
+        let mut senders = Vec::new();
+        let mut handles = Vec::new();
+        for (address, node) in self.nodes.into_iter().enumerate() {
+            let (sender, receiver) = channel();
+            for packet in node.queue {
+                in_flight.fetch_add(1, Ordering::SeqCst);
+                sender.send(packet).unwrap();
+            }
+            senders.push(sender);
+
+            let mut input = ThreadInput {
+                address,
+                receiver,
+                pending: None,
+                empty_reads: 0,
+                stop: stop.clone(),
+                in_flight: in_flight.clone(),
+                idle: idle.clone(),
+            };
+            let mut output = ThreadOutput {
+                address,
+                partial: node.partial,
+                router: router.clone(),
+                idle: idle.clone(),
+                stop: stop.clone(),
+                error: None,
+            };
+            let mut machine = node.machine;
+            handles.push(thread::spawn(move || {
+                let result = run_machine(&mut machine, &mut input, &mut output);
+                // A finished machine will never send anything again
+                output.idle[address].store(true, Ordering::SeqCst);
+                result
+            }));
+        }
+        // Only the machines hold senders now, so the router hangs up once they all finish
+        drop(router);
+
+        let route = |packet: Packet, monitor: &mut M| -> Control {
+            let mut next = Some(packet);
+            while let Some(packet) = next.take() {
+                monitor.packet(&packet);
+                if packet.destination < size {
+                    // Counted before sending, so the machine can't take it off the
+                    // count first. A machine that has finished never receives it.
+                    in_flight.fetch_add(1, Ordering::SeqCst);
+                    if senders[packet.destination].send(packet).is_err() {
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    }
+                } else {
+                    match monitor.unrouted(packet) {
+                        Control::Continue => {}
+                        Control::Send(packet) => next = Some(packet),
+                        Control::Stop => return Control::Stop,
+                    }
+                }
+            }
+            Control::Continue
+        };
+
+        loop {
+            match packets.recv_timeout(Duration::from_millis(1)) {
+                Ok(packet) => {
+                    if route(packet, monitor) == Control::Stop {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let quiet = in_flight.load(Ordering::SeqCst) == 0
+                        && idle.iter().all(|flag| flag.load(Ordering::SeqCst));
+                    if !quiet {
+                        continue;
+                    }
+
+                    // A packet may have been sent just before its machine went idle
+                    let control = match packets.try_recv() {
+                        Ok(packet) => route(packet, monitor),
+                        Err(_) => match monitor.idle() {
+                            Control::Send(packet) => route(packet, monitor),
+                            control => control,
+                        },
+                    };
+                    if control == Control::Stop {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        stop.store(true, Ordering::SeqCst);
+        let mut result = Ok(());
+        for handle in handles {
+            let machine_result = handle.join().expect("a machine thread panicked");
+            if let (Ok(()), Err(e)) = (&result, machine_result) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+/// How many instructions a machine on its own thread runs between checks for
+/// the network stopping
+const BATCH: u64 = 10_000;
+
+/// Run a machine on its own thread until it halts, runs out of input, or the
+/// network stops. The machine runs in batches of steps, so it notices the
+/// network stopping even while it is computing rather than reading input. Its
+/// own step limit, if it has one, still applies.
+fn run_machine(
+    machine: &mut Intcode,
+    input: &mut ThreadInput,
+    output: &mut ThreadOutput,
+) -> Result<(), IntcodeError> {
+    let limit = machine.step_limit;
+    while !input.stop.load(Ordering::SeqCst) {
+        let batch = machine.steps() + BATCH;
+        machine.set_step_limit(Some(limit.map_or(batch, |limit| limit.min(batch))));
+        let result = machine.run_with(&mut *input, &mut *output);
+        if let Some(e) = output.error.take() {
+            return Err(e);
+        }
+        match result {
+            Err(IntcodeError::StepLimit { .. })
+                if limit.is_none_or(|limit| machine.steps() < limit) => {}
+            result => return result.map(|_| ()),
+        }
+    }
+    Ok(())
+}
+
+/// Input for a machine running on its own thread
+struct ThreadInput {
+    address: usize,
+    receiver: Receiver<Packet>,
+    // The Y value of a packet whose X value has already been read
+    pending: Option<isize>,
+    empty_reads: usize,
+    stop: Arc<AtomicBool>,
+    in_flight: Arc<AtomicUsize>,
+    idle: Arc<Vec<AtomicBool>>,
+}
+
+impl InputSource for ThreadInput {
+    fn next_input(&mut self) -> Option<isize> {
+        if self.stop.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        if let Some(y) = self.pending.take() {
+            return Some(y);
+        }
+
+        match self.receiver.try_recv() {
+            Ok(packet) => {
+                self.empty_reads = 0;
+                self.idle[self.address].store(false, Ordering::SeqCst);
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                self.pending = Some(packet.y);
+                Some(packet.x)
+            }
+            Err(TryRecvError::Empty) => {
+                self.empty_reads += 1;
+                if self.empty_reads >= 2 {
+                    self.idle[self.address].store(true, Ordering::SeqCst);
+                }
+                thread::yield_now();
+                Some(-1)
+            }
+            Err(TryRecvError::Disconnected) => None,
+        }
+    }
+}
+
+/// Output for a machine running on its own thread
+struct ThreadOutput {
+    address: usize,
+    partial: Vec<isize>,
+    router: Sender<Packet>,
+    idle: Arc<Vec<AtomicBool>>,
+    stop: Arc<AtomicBool>,
+    // The first bad packet, which stops the network
+    error: Option<IntcodeError>,
+}
+
+impl OutputSink for ThreadOutput {
+    fn send_output(&mut self, value: isize) {
+        self.idle[self.address].store(false, Ordering::SeqCst);
+        self.partial.push(value);
+        if self.partial.len() == 3 {
+            match Packet::from_output(self.address, &self.partial) {
+                Ok(packet) => {
+                    let _ = self.router.send(packet);
+                }
+                Err(e) => {
+                    self.error.get_or_insert(e);
+                    self.stop.store(true, Ordering::SeqCst);
+                }
+            }
+            self.partial.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    // Each machine passes any packet it receives on to the next address with X
+    // incremented. The last of four machines sends to address 255 instead.
+    const RELAY: &str = "
+                IN   addr
+        loop:   IN   x
+                EQ   x, #-1, tmp
+                JNZ  tmp, #loop
+                IN   y
+                ADD  addr, #1, dest
+                LT   dest, #4, tmp
+                JNZ  tmp, #send
+                ADD  #255, #0, dest
+        send:   OUT  dest
+                ADD  x, #1, x
+                OUT  x
+                OUT  y
+                JZ   #0, #loop
+        addr:   data 0
+        x:      data 0
+        y:      data 0
+        dest:   data 0
+        tmp:    data 0
+    ";
+
+    fn relay_network() -> Network {
+        let machine = Intcode::new(&assemble(RELAY).unwrap()).unwrap();
+        let mut network = Network::new(&machine, 4);
+        network.send(Packet {
+            destination: 0,
+            x: 10,
+            y: 20,
+        });
+        network
+    }
+
+    /// Records the first packet sent to 255 and stops
+    #[derive(Default)]
+    struct FirstUnrouted(Option<Packet>);
+
+    impl Monitor for FirstUnrouted {
+        fn unrouted(&mut self, packet: Packet) -> Control {
+            self.0 = Some(packet);
+            Control::Stop
+        }
+    }
+
+    /// Like the NAT from day 23: remembers the last packet sent to 255, and sends it
+    /// to address 0 whenever the network is idle. Stops when it sends the same Y twice
+    /// in a row.
+    #[derive(Default)]
+    struct Nat {
+        last: Option<Packet>,
+        sent: Vec<isize>,
+        routed: usize,
+    }
+
+    impl Monitor for Nat {
+        fn packet(&mut self, _packet: &Packet) {
+            self.routed += 1;
+        }
+
+        fn unrouted(&mut self, packet: Packet) -> Control {
+            self.last = Some(packet);
+            Control::Continue
+        }
+
+        fn idle(&mut self) -> Control {
+            let last = match self.last {
+                Some(last) => last,
+                None => return Control::Continue,
+            };
+            if self.sent.last() == Some(&last.y) {
+                return Control::Stop;
+            }
+            self.sent.push(last.y);
+            Control::Send(Packet {
+                destination: 0,
+                ..last
+            })
+        }
+    }
+
+    #[test]
+    fn routes_packets() {
+        let mut network = relay_network();
+        let mut monitor = FirstUnrouted::default();
+        network.run(&mut monitor).unwrap();
+        assert_eq!(
+            monitor.0,
+            Some(Packet {
+                destination: 255,
+                x: 14,
+                y: 20
+            })
+        );
+    }
+
+    #[test]
+    fn idle_detection() {
+        let mut network = relay_network();
+        let mut nat = Nat::default();
+        network.run(&mut nat).unwrap();
+        assert_eq!(
+            nat.last,
+            Some(Packet {
+                destination: 255,
+                x: 18,
+                y: 20
+            })
+        );
+        assert_eq!(nat.sent, vec![20]);
+        // Two laps of the ring, plus the packet the NAT sent
+        assert_eq!(nat.routed, 9);
+    }
+
+    #[test]
+    fn idle_waits_for_empty_reads() {
+        // With no packets at all, every machine reads its address, then -1,
+        // before the network is idle
+        let machine = Intcode::new(&assemble(RELAY).unwrap()).unwrap();
+        let mut network = Network::new(&machine, 4);
+        network.run(&mut ()).unwrap();
+        for address in 0..4 {
+            // IN addr, then IN x, EQ and JNZ with x = -1
+            assert_eq!(network.machine(address).unwrap().steps(), 4);
+        }
+    }
+
+    #[test]
+    fn threaded() {
+        let network = relay_network();
+        let mut monitor = FirstUnrouted::default();
+        network.run_threaded(&mut monitor).unwrap();
+        assert_eq!(
+            monitor.0,
+            Some(Packet {
+                destination: 255,
+                x: 14,
+                y: 20
+            })
+        );
+    }
+
+    #[test]
+    fn threaded_idle_detection() {
+        let network = relay_network();
+        let mut nat = Nat::default();
+        network.run_threaded(&mut nat).unwrap();
+        assert_eq!(nat.sent, vec![20]);
+        assert_eq!(nat.last.map(|packet| packet.x), Some(18));
+    }
+
+    #[test]
+    fn negative_destination() {
+        // IN a / OUT #-1 / OUT #1 / OUT #2 / HLT / a: data 0
+        let machine = Intcode::new("3,9,104,-1,104,1,104,2,99,0").unwrap();
+        let error = Err(IntcodeError::NegativeDestination {
+            machine: 0,
+            destination: -1,
+        });
+        let mut network = Network::new(&machine, 2);
+        assert_eq!(network.run(&mut ()), error);
+        let network = Network::new(&machine, 2);
+        assert_eq!(network.run_threaded(&mut ()), error);
+    }
+
+    #[test]
+    fn threaded_stops_busy_machines() {
+        // IN a / OUT #255 / OUT a / OUT #0 / loop: JZ #0, #loop / a: data 0
+        // Every machine reports to 255 and then spins without reading input
+        let machine = Intcode::new("3,11,104,255,4,11,104,0,1106,0,8,0").unwrap();
+        let network = Network::new(&machine, 2);
+        let mut monitor = FirstUnrouted::default();
+        network.run_threaded(&mut monitor).unwrap();
+        assert_eq!(monitor.0.map(|packet| packet.destination), Some(255));
+    }
+
+    #[test]
+    fn stops_when_all_halted() {
+        let machine = Intcode::new("3,0,99").unwrap();
+        let mut network = Network::new(&machine, 3);
+        network.run(&mut ()).unwrap();
+        assert!(network.machine(2).unwrap().is_halted());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::instruction::{mnemonic, write_operand};
+use crate::{Intcode, Operation, Word};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Counts of what a machine did while it ran, for finding where the time goes
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Profile {
+    total: u64,
+    opcodes: BTreeMap<usize, u64>,
+    // The opcode last executed at each address, and how many times it ran
+    addresses: HashMap<usize, (usize, u64)>,
+    reads: HashMap<usize, u64>,
+    writes: HashMap<usize, u64>,
+    back_edges: HashMap<(usize, usize), u64>,
+}
+
+impl Profile {
+    /// Count an operation that was just executed from the given address, leaving
+    /// the pointer at `next`
+    pub(crate) fn record(
+        &mut self,
+        pointer: usize,
+        raw_opcode: isize,
+        operation: &Operation,
+        next: usize,
+    ) {
+        let opcode = operation.opcode();
+        self.total += 1;
+        *self.opcodes.entry(opcode).or_insert(0) += 1;
+        let executed = self.addresses.entry(pointer).or_insert((opcode, 0));
+        *executed = (opcode, executed.1 + 1);
+
+        // Immediate operands are part of the instruction, not memory accesses
+        let mut modes = raw_opcode / 100;
+        for (i, location) in operation.operand_locations().iter().enumerate() {
+            if modes % 10 != 1 {
+                let counts = if write_operand(opcode) == Some(i) {
+                    &mut self.writes
+                } else {
+                    &mut self.reads
+                };
+                *counts.entry(*location).or_insert(0) += 1;
+            }
+            modes /= 10;
+        }
+
+        // A jump that went backwards closes a loop
+        if (opcode == 5 || opcode == 6) && next <= pointer {
+            *self.back_edges.entry((pointer, next)).or_insert(0) += 1;
+        }
+    }
+
+    /// The total number of instructions executed
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// How many instructions with the given opcode were executed
+    pub fn opcode_count(&self, opcode: usize) -> u64 {
+        *self.opcodes.get(&opcode).unwrap_or(&0)
+    }
+
+    /// How many times the instruction at the given address was executed
+    pub fn executions(&self, address: usize) -> u64 {
+        self.addresses.get(&address).map_or(0, |(_, count)| *count)
+    }
+
+    /// How many times instructions read the given memory cell
+    pub fn reads(&self, address: usize) -> u64 {
+        *self.reads.get(&address).unwrap_or(&0)
+    }
+
+    /// How many times instructions wrote the given memory cell
+    pub fn writes(&self, address: usize) -> u64 {
+        *self.writes.get(&address).unwrap_or(&0)
+    }
+
+    /// The most executed instruction addresses with their counts, busiest first
+    pub fn hot_addresses(&self, top: usize) -> Vec<(usize, u64)> {
+        busiest(
+            self.addresses
+                .iter()
+                .map(|(address, (_, count))| (*address, *count)),
+            top,
+        )
+    }
+
+    /// The most accessed memory cells with their combined read and write counts,
+    /// busiest first
+    pub fn hot_cells(&self, top: usize) -> Vec<(usize, u64)> {
+        let mut accesses = self.reads.clone();
+        for (address, count) in &self.writes {
+            *accesses.entry(*address).or_insert(0) += count;
+        }
+        busiest(accesses.into_iter(), top)
+    }
+
+    /// Every backwards jump taken, as the jump's address and its target, along with
+    /// how many times it was taken, busiest first
+    pub fn back_edges(&self) -> Vec<((usize, usize), u64)> {
+        busiest(self.back_edges.iter().map(|(e, c)| (*e, *c)), usize::MAX)
+    }
+
+    /// Render a human readable summary showing the busiest opcodes, and the `top`
+    /// busiest addresses, memory cells, and loops
+    pub fn report(&self, top: usize) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+        let mut report = format!("{} instructions executed\n", self.total);
+
+        report.push_str("\nOpcodes:\n");
+        let opcodes = busiest(self.opcodes.iter().map(|(o, c)| (*o, *c)), usize::MAX);
+        for (opcode, count) in opcodes {
+            let name = mnemonic(opcode).unwrap_or("???");
+            writeln!(
+                report,
+                "  {:<4} {:>12} {:>6.2}%",
+                name,
+                count,
+                percent(count)
+            )
+            .unwrap();
+        }
+
+        report.push_str("\nHot addresses:\n");
+        for (address, count) in self.hot_addresses(top) {
+            let name = mnemonic(self.addresses[&address].0).unwrap_or("???");
+            writeln!(
+                report,
+                "  {:>5}: {:<4} {:>12} {:>6.2}%",
+                address,
+                name,
+                count,
+                percent(count)
+            )
+            .unwrap();
+        }
+
+        report.push_str("\nHot cells:\n");
+        for (address, _) in self.hot_cells(top) {
+            writeln!(
+                report,
+                "  {:>5}: {:>12} reads {:>12} writes",
+                address,
+                self.reads(address),
+                self.writes(address)
+            )
+            .unwrap();
+        }
+
+        report.push_str("\nLoops:\n");
+        for ((from, to), count) in self.back_edges().into_iter().take(top) {
+            writeln!(report, "  {:>5} -> {:<5} {:>12} times", from, to, count).unwrap();
+        }
+
+        report
+    }
+}
+
+/// Sort counts by busiest first, breaking ties by key, and keep the first `top`
+fn busiest<K: Ord>(counts: impl Iterator<Item = (K, u64)>, top: usize) -> Vec<(K, u64)> {
+    let mut counts = counts.collect::<Vec<_>>();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    counts.truncate(top);
+    counts
+}
+
+impl<W: Word> Intcode<W> {
+    /// Start counting the instructions this machine executes and the memory they
+    /// touch. Any previous profile is discarded.
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    /// The profile recorded so far, if profiling is enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Stop profiling, returning the profile recorded so far
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IN c / loop: ADD c, #-1, c / JNZ c, #loop / OUT c / HLT / c: data 0
+    const COUNTDOWN: &str = "3,12,1001,12,-1,12,1005,12,2,4,12,99,0";
+
+    fn countdown(from: isize) -> Profile {
+        let mut machine = Intcode::new(COUNTDOWN).unwrap();
+        machine.enable_profile();
+        machine.push_input(from);
+        machine.execute().unwrap();
+        machine.take_profile().unwrap()
+    }
+
+    #[test]
+    fn counts_instructions_and_memory() {
+        let profile = countdown(10);
+        assert_eq!(profile.total(), 22);
+        assert_eq!(profile.opcode_count(1), 10);
+        assert_eq!(profile.opcode_count(5), 10);
+        assert_eq!(profile.executions(0), 1);
+        assert_eq!(profile.executions(2), 10);
+
+        // The counter is read by every ADD, JNZ and the OUT, and written by the IN
+        // and every ADD. The immediate -1 is not a memory access.
+        assert_eq!(profile.reads(12), 21);
+        assert_eq!(profile.writes(12), 11);
+        assert_eq!(profile.reads(4), 0);
+    }
+
+    #[test]
+    fn finds_hot_spots() {
+        let profile = countdown(10);
+        assert_eq!(profile.hot_addresses(2), vec![(2, 10), (6, 10)]);
+        assert_eq!(profile.hot_cells(1), vec![(12, 32)]);
+        assert_eq!(profile.back_edges(), vec![((6, 2), 9)]);
+
+        let report = profile.report(1);
+        assert!(report.starts_with("22 instructions executed\n"));
+        assert!(report.contains("      2: ADD            10  45.45%\n"));
+        assert!(report.contains("      6 -> 2                9 times\n"));
+    }
+}
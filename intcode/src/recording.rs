@@ -0,0 +1,352 @@
+use crate::{Intcode, IntcodeError, Word};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// The first line of every recording. The number goes up if the format changes.
+const HEADER: &str = "intcode recording 1";
+
+/// Why a recording could not be parsed. Line numbers are one-based.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum RecordingError {
+    /// The text does not start with a recording header this version understands
+    BadHeader { header: String },
+    /// The line is not a step number followed by an input value
+    InvalidLine { line: usize, text: String },
+    /// The line's step does not come after the step on the line before
+    OutOfOrder { line: usize },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordingError::BadHeader { header } => {
+                write!(f, "Not an Intcode recording: {:?}", header)
+            }
+            RecordingError::InvalidLine { line, text } => {
+                write!(
+                    f,
+                    "Line {}: expected a step and a value, found {:?}",
+                    line, text
+                )
+            }
+            RecordingError::OutOfOrder { line } => {
+                write!(f, "Line {}: step is not after the previous one", line)
+            }
+        }
+    }
+}
+
+impl Error for RecordingError {}
+
+/// Every input value a machine consumed, along with the step it was consumed at
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Recording<W = isize> {
+    inputs: Vec<(u64, W)>,
+}
+
+impl<W> Default for Recording<W> {
+    fn default() -> Self {
+        Self { inputs: Vec::new() }
+    }
+}
+
+impl<W: Word> Recording<W> {
+    /// The recorded inputs, as step numbers and values in the order consumed
+    pub fn inputs(&self) -> &[(u64, W)] {
+        &self.inputs
+    }
+
+    /// Add an input consumed at the given step
+    pub(crate) fn push(&mut self, step: u64, value: W) {
+        self.inputs.push((step, value));
+    }
+
+    /// Remove the input consumed at the given step, if it is the last one
+    /// recorded
+    pub(crate) fn unrecord(&mut self, step: u64) {
+        if self.inputs.last().map(|(last, _)| *last) == Some(step) {
+            self.inputs.pop();
+        }
+    }
+
+    /// Render the recording as text: a header line, then one line per input
+    /// holding the step number and the value
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+        for (step, value) in &self.inputs {
+            writeln!(text, "{} {}", step, value).unwrap();
+        }
+        text
+    }
+
+    /// Parse a recording from the text written by `to_text`
+    pub fn parse(text: &str) -> Result<Self, RecordingError> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, header)) if header.trim_end() == HEADER => {}
+            other => {
+                return Err(RecordingError::BadHeader {
+                    header: other.map_or("", |(_, header)| header).to_string(),
+                })
+            }
+        }
+
+        let mut recording = Self::default();
+        for (index, text) in lines {
+            if text.trim().is_empty() {
+                continue;
+            }
+            let line = index + 1;
+            let invalid = || RecordingError::InvalidLine {
+                line,
+                text: text.to_string(),
+            };
+            let mut parts = text.split_whitespace();
+            let step: u64 = parts
+                .next()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(invalid)?;
+            let value = parts
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(invalid)?;
+            if parts.next().is_some() {
+                return Err(invalid());
+            }
+            if recording
+                .inputs
+                .last()
+                .is_some_and(|(last, _)| step <= *last)
+            {
+                return Err(RecordingError::OutOfOrder { line });
+            }
+            recording.push(step, value);
+        }
+        Ok(recording)
+    }
+
+    /// Write the recording to the given file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+
+    /// Read a recording from the given file. A file that isn't a valid recording
+    /// gives an `InvalidData` error wrapping the `RecordingError`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Why a replay stopped without an error
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum ReplayStop {
+    /// The machine reached a halt instruction
+    Halted,
+    /// The machine needs input, and the recording has none left
+    EndOfRecording,
+    /// The machine reached the step it was asked to stop at
+    ReachedStep,
+}
+
+impl<W: Word> Intcode<W> {
+    /// Start recording every input value this machine consumes. Any previous
+    /// recording is discarded.
+    pub fn enable_recording(&mut self) {
+        self.recording = Some(Recording::default());
+    }
+
+    /// The inputs recorded so far, if recording is enabled
+    pub fn recording(&self) -> Option<&Recording<W>> {
+        self.recording.as_ref()
+    }
+
+    /// Stop recording, returning the inputs recorded so far
+    pub fn take_recording(&mut self) -> Option<Recording<W>> {
+        self.recording.take()
+    }
+
+    /// Run the machine, feeding it each recorded input at the step it was
+    /// originally consumed at, so that a session replays exactly. Stops before
+    /// executing step `until` if it is given. Output is collected on the output
+    /// tape. The machine should start with an empty input queue, at or before the
+    /// first recorded step.
+    ///
+    /// Fails with `IntcodeError::Diverged` if the machine needs input at a step
+    /// with no recorded value, or reaches a recorded step at an instruction that
+    /// isn't an input, since then the replay no longer matches the session.
+    pub fn replay(
+        &mut self,
+        recording: &Recording<W>,
+        until: Option<u64>,
+    ) -> Result<ReplayStop, IntcodeError> {
+        let start = recording
+            .inputs
+            .iter()
+            .position(|(step, _)| *step >= self.steps)
+            .unwrap_or(recording.inputs.len());
+        let mut inputs = recording.inputs[start..].iter().peekable();
+
+        loop {
+            if until.is_some_and(|until| self.steps >= until) {
+                return Ok(ReplayStop::ReachedStep);
+            }
+            let operation = self.fetch()?;
+            if operation.opcode == 99 {
+                return Ok(ReplayStop::Halted);
+            }
+
+            let diverged = IntcodeError::Diverged {
+                pointer: self.pointer,
+                step: self.steps,
+            };
+            match inputs.peek() {
+                Some((step, value)) if *step == self.steps => {
+                    if operation.opcode != 3 {
+                        return Err(diverged);
+                    }
+                    self.input.push_back(value.clone());
+                    inputs.next();
+                }
+                Some(_) if operation.opcode == 3 && self.input.is_empty() => return Err(diverged),
+                None if operation.opcode == 3 && self.input.is_empty() => {
+                    return Ok(ReplayStop::EndOfRecording)
+                }
+                _ => {}
+            }
+
+            if let Some(output_value) = self.execute_operation(&operation)? {
+                self.output.push_back(output_value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loop: IN x / JZ x, #end / OUT x / JZ #0, #loop / end: HLT / x: data 0
+    const ECHO: &str = "3,11,1006,11,10,4,11,1106,0,0,99,0";
+
+    /// Run the echo program interactively, feeding it one value at a time
+    fn session(values: &[isize]) -> Intcode {
+        let mut machine = Intcode::new(ECHO).unwrap();
+        machine.enable_recording();
+        for value in values {
+            machine.execute().unwrap();
+            machine.push_input(*value);
+        }
+        machine.execute().unwrap();
+        machine
+    }
+
+    #[test]
+    fn records_inputs_with_steps() {
+        let mut machine = session(&[5, 6, 0]);
+        let recording = machine.take_recording().unwrap();
+        assert_eq!(recording.inputs(), &[(0, 5), (4, 6), (8, 0)]);
+        assert_eq!(recording.to_text(), "intcode recording 1\n0 5\n4 6\n8 0\n");
+        assert_eq!(Recording::parse(&recording.to_text()), Ok(recording));
+    }
+
+    #[test]
+    fn replays_session() {
+        let mut original = session(&[5, 6, 0]);
+        let recording = original.take_recording().unwrap();
+
+        let mut machine = Intcode::new(ECHO).unwrap();
+        assert_eq!(machine.replay(&recording, None), Ok(ReplayStop::Halted));
+        assert_eq!(machine.get_output(), vec![5, 6]);
+        assert!(machine == original);
+    }
+
+    #[test]
+    fn replay_stops_at_step() {
+        let recording = session(&[5, 6, 0]).take_recording().unwrap();
+        let mut machine = Intcode::new(ECHO).unwrap();
+        assert_eq!(
+            machine.replay(&recording, Some(7)),
+            Ok(ReplayStop::ReachedStep)
+        );
+        assert_eq!(machine.steps(), 7);
+        assert_eq!(machine.get_output(), vec![5, 6]);
+
+        // Carry on from where it stopped
+        assert_eq!(machine.replay(&recording, None), Ok(ReplayStop::Halted));
+        assert_eq!(machine.steps(), 10);
+    }
+
+    #[test]
+    fn replay_runs_out_and_diverges() {
+        let recording = session(&[5, 6]).take_recording().unwrap();
+        let mut machine = Intcode::new(ECHO).unwrap();
+        assert_eq!(
+            machine.replay(&recording, None),
+            Ok(ReplayStop::EndOfRecording)
+        );
+        assert_eq!(machine.steps(), 8);
+
+        // A different program consumes input at different steps
+        let mut machine = Intcode::new("1101,0,0,0,3,0,99").unwrap();
+        assert_eq!(
+            machine.replay(&recording, None),
+            Err(IntcodeError::Diverged {
+                pointer: 0,
+                step: 0
+            })
+        );
+    }
+
+    #[test]
+    fn step_back_unrecords_input() {
+        let mut machine = Intcode::new(ECHO).unwrap();
+        machine.enable_recording();
+        machine.enable_history(100);
+        machine.push_input(5);
+        machine.execute().unwrap();
+        assert_eq!(machine.recording().unwrap().inputs(), &[(0, 5)]);
+
+        // Undoing the input puts it back on the queue, so it is recorded once
+        machine.step_back(4);
+        assert!(machine.recording().unwrap().inputs().is_empty());
+        machine.execute().unwrap();
+        assert_eq!(machine.recording().unwrap().inputs(), &[(0, 5)]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("intcode-recording-{}", std::process::id()));
+        let recording = session(&[5, 6, 0]).take_recording().unwrap();
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), recording);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |text: &str| Recording::<isize>::parse(text).err().unwrap();
+        assert_eq!(
+            parse("0 5\n"),
+            RecordingError::BadHeader {
+                header: "0 5".to_string()
+            }
+        );
+        assert_eq!(
+            parse("intcode recording 1\n0 5\nfour 6\n"),
+            RecordingError::InvalidLine {
+                line: 3,
+                text: "four 6".to_string()
+            }
+        );
+        assert_eq!(
+            parse("intcode recording 1\n4 5\n4 6\n"),
+            RecordingError::OutOfOrder { line: 3 }
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use crate::memory::Memory;
+use crate::{Intcode, Word};
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Write};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+// The first line of every snapshot. The number goes up if the format changes.
+const HEADER: &str = "intcode snapshot 1";
+
+/// Why a snapshot could not be restored. Line numbers are one-based.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum SnapshotError {
+    /// The text does not start with a snapshot header this version understands
+    BadHeader { header: String },
+    /// The line starts with a field name that isn't part of a snapshot
+    UnknownField { line: usize, field: String },
+    /// The value of the field on the given line could not be parsed
+    InvalidValue {
+        line: usize,
+        field: String,
+        value: String,
+    },
+    /// The snapshot has no line for the field
+    MissingField { field: &'static str },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadHeader { header } => {
+                write!(f, "Not an Intcode snapshot: {:?}", header)
+            }
+            SnapshotError::UnknownField { line, field } => {
+                write!(f, "Line {}: unknown field {:?}", line, field)
+            }
+            SnapshotError::InvalidValue { line, field, value } => {
+                write!(f, "Line {}: invalid {} {:?}", line, field, value)
+            }
+            SnapshotError::MissingField { field } => write!(f, "Missing field {:?}", field),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Add a field to a snapshot
+fn field(snapshot: &mut String, name: &str, value: impl Display) {
+    let value = value.to_string();
+    if value.is_empty() {
+        writeln!(snapshot, "{}", name).unwrap();
+    } else {
+        writeln!(snapshot, "{} {}", name, value).unwrap();
+    }
+}
+
+/// Join values with commas
+fn list<T: Display>(values: impl Iterator<Item = T>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// Parse a comma separated list, which may be empty
+fn parse_list<T: FromStr>(value: &str) -> Option<Vec<T>> {
+    if value.is_empty() {
+        return Some(Vec::new());
+    }
+    value.split(',').map(|v| v.trim().parse().ok()).collect()
+}
+
+/// Parse a comma separated list of `address=value` cells, which may be empty
+fn parse_cells<W: Word>(value: &str) -> Option<Vec<(usize, W)>> {
+    if value.is_empty() {
+        return Some(Vec::new());
+    }
+    value
+        .split(',')
+        .map(|cell| {
+            let mut parts = cell.splitn(2, '=');
+            let address = parts.next()?.trim().parse().ok()?;
+            let value = parts.next()?.trim().parse().ok()?;
+            Some((address, value))
+        })
+        .collect()
+}
+
+impl<W: Word> Intcode<W> {
+    /// Capture the state of the machine as text: its memory, pointer, relative
+    /// base, step count, and any input and output still queued. Settings like
+    /// checked arithmetic, limits, tracing and profiling are not included.
+    ///
+    /// The format is a header line followed by one `name value` line per field,
+    /// with lists written comma separated as in a program.
+    pub fn snapshot(&self) -> String {
+        let mut snapshot = format!("{}\n", HEADER);
+        field(&mut snapshot, "pointer", self.pointer);
+        field(&mut snapshot, "relative_base", self.relative_base);
+        field(&mut snapshot, "steps", self.steps);
+        field(&mut snapshot, "input", list(self.input.iter()));
+        field(&mut snapshot, "output", list(self.output.iter()));
+        field(&mut snapshot, "memory", list(self.memory.iter()));
+        let sparse = self.memory.sparse_cells().into_iter();
+        let cells = sparse.map(|(address, value)| format!("{}={}", address, value));
+        field(&mut snapshot, "sparse", list(cells));
+        snapshot
+    }
+
+    /// Restore a machine from a snapshot. The machine starts with every setting
+    /// off, as if it had just been created.
+    pub fn from_snapshot(snapshot: &str) -> Result<Self, SnapshotError> {
+        let mut lines = snapshot.lines().enumerate();
+        match lines.next() {
+            Some((_, header)) if header.trim_end() == HEADER => {}
+            other => {
+                return Err(SnapshotError::BadHeader {
+                    header: other.map_or("", |(_, header)| header).to_string(),
+                })
+            }
+        }
+
+        let mut pointer = None;
+        let mut relative_base = None;
+        let mut steps = None;
+        let mut input = None;
+        let mut output = None;
+        let mut memory = None;
+        let mut sparse = None;
+        for (index, text) in lines {
+            if text.trim().is_empty() {
+                continue;
+            }
+            let line = index + 1;
+            let text = text.trim();
+            let (name, value) = text.split_once(' ').unwrap_or((text, ""));
+            let value = value.trim();
+            let invalid = || SnapshotError::InvalidValue {
+                line,
+                field: name.to_string(),
+                value: value.to_string(),
+            };
+            match name {
+                "pointer" => pointer = Some(value.parse().map_err(|_| invalid())?),
+                "relative_base" => relative_base = Some(value.parse().map_err(|_| invalid())?),
+                "steps" => steps = Some(value.parse().map_err(|_| invalid())?),
+                "input" => input = Some(parse_list(value).ok_or_else(invalid)?),
+                "output" => output = Some(parse_list(value).ok_or_else(invalid)?),
+                "memory" => memory = Some(parse_list(value).ok_or_else(invalid)?),
+                "sparse" => sparse = Some(parse_cells(value).ok_or_else(invalid)?),
+                _ => {
+                    return Err(SnapshotError::UnknownField {
+                        line,
+                        field: name.to_string(),
+                    })
+                }
+            }
+        }
+
+        let missing = |field| SnapshotError::MissingField { field };
+        let mut memory = Memory::from(memory.ok_or_else(|| missing("memory"))?);
+        for (address, value) in sparse.ok_or_else(|| missing("sparse"))? {
+            memory.write(address, value);
+        }
+        let mut machine = Self::from_memory(memory);
+        machine.pointer = pointer.ok_or_else(|| missing("pointer"))?;
+        machine.relative_base = relative_base.ok_or_else(|| missing("relative_base"))?;
+        machine.steps = steps.ok_or_else(|| missing("steps"))?;
+        machine.input = input.ok_or_else(|| missing("input"))?.into();
+        machine.output = output.ok_or_else(|| missing("output"))?.into();
+        Ok(machine)
+    }
+
+    /// Write a snapshot of the machine to the given file
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.snapshot())
+    }
+
+    /// Restore a machine from a snapshot file. A file that isn't a valid
+    /// snapshot gives an `InvalidData` error wrapping the `SnapshotError`.
+    pub fn load_snapshot<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let snapshot = fs::read_to_string(path)?;
+        Self::from_snapshot(&snapshot).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ARB #5 / ADD #2, #3, 1000000000 / OUT #9 / IN @20 / OUT @20 / HLT
+    const PROGRAM: &str = "109,5,1101,2,3,1000000000,104,9,203,20,204,20,99";
+
+    /// A machine part way through the program, with input and output queued
+    fn paused() -> Intcode {
+        let mut machine = Intcode::new(PROGRAM).unwrap();
+        machine.push_input(7);
+        machine.push_input(8);
+        for _ in 0..3 {
+            machine.step_mut().unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn round_trip() {
+        let machine = paused();
+        let snapshot = machine.snapshot();
+        assert_eq!(
+            snapshot,
+            "intcode snapshot 1\n\
+             pointer 8\n\
+             relative_base 5\n\
+             steps 3\n\
+             input 7,8\n\
+             output 9\n\
+             memory 109,5,1101,2,3,1000000000,104,9,203,20,204,20,99\n\
+             sparse 1000000000=5\n"
+        );
+
+        let mut restored = Intcode::from_snapshot(&snapshot).unwrap();
+        assert!(restored == machine);
+        restored.execute().unwrap();
+        assert_eq!(restored.get_output(), vec![9, 7]);
+        assert_eq!(restored.read(25), 7);
+    }
+
+    #[test]
+    fn empty_fields() {
+        let machine = Intcode::new("99").unwrap();
+        let snapshot = machine.snapshot();
+        assert!(snapshot.contains("\ninput\noutput\nmemory 99\nsparse\n"));
+        assert!(Intcode::from_snapshot(&snapshot).unwrap() == machine);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("intcode-snapshot-{}", std::process::id()));
+        let machine = paused();
+        machine.save_snapshot(&path).unwrap();
+        let restored = Intcode::load_snapshot(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(restored.unwrap() == machine);
+    }
+
+    #[test]
+    fn errors() {
+        let snapshot = paused().snapshot();
+        let error = |text: &str| Intcode::<isize>::from_snapshot(text).err().unwrap();
+
+        assert_eq!(
+            error("1,2,3"),
+            SnapshotError::BadHeader {
+                header: "1,2,3".to_string()
+            }
+        );
+        assert_eq!(
+            error(&snapshot.replace("steps", "stops")),
+            SnapshotError::UnknownField {
+                line: 4,
+                field: "stops".to_string()
+            }
+        );
+        assert_eq!(
+            error(&snapshot.replace("7,8", "7,x")),
+            SnapshotError::InvalidValue {
+                line: 5,
+                field: "input".to_string(),
+                value: "7,x".to_string()
+            }
+        );
+        assert_eq!(
+            error(&snapshot.replace("output 9\n", "")),
+            SnapshotError::MissingField { field: "output" }
+        );
+
+        let path = std::env::temp_dir().join("intcode-snapshot-missing");
+        let io_error = Intcode::<isize>::load_snapshot(path).err().unwrap();
+        assert_eq!(io_error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,578 @@
+use crate::error::IntcodeError;
+use crate::instruction::{write_operand, Instruction, Mode};
+use crate::memory::Memory;
+use crate::Intcode;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+/// A value built up by symbolic execution. Operations on constants are folded
+/// as they are built, so a program that never touches a symbol only ever
+/// produces constants.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Expr {
+    Const(isize),
+    Symbol(Rc<str>),
+    Add(Rc<Expr>, Rc<Expr>),
+    Mul(Rc<Expr>, Rc<Expr>),
+    LessThan(Rc<Expr>, Rc<Expr>),
+    Equals(Rc<Expr>, Rc<Expr>),
+    /// The value of memory at an address that depends on a symbol
+    Load(Rc<Expr>),
+}
+
+impl Expr {
+    /// A named unknown value
+    pub fn symbol(name: &str) -> Self {
+        Expr::Symbol(name.into())
+    }
+
+    /// One if the first expression is less than the second, otherwise zero
+    pub fn less_than(a: Expr, b: Expr) -> Self {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as isize),
+            (a, b) => Expr::LessThan(Rc::new(a), Rc::new(b)),
+        }
+    }
+
+    /// One if the expressions are equal, otherwise zero
+    pub fn equals(a: Expr, b: Expr) -> Self {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as isize),
+            (a, b) => Expr::Equals(Rc::new(a), Rc::new(b)),
+        }
+    }
+
+    /// The value, if it doesn't depend on any symbol
+    pub fn as_const(&self) -> Option<isize> {
+        match self {
+            Expr::Const(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The sum of two expressions. Returns `None` if they are constants whose
+    /// sum overflows.
+    pub fn checked_add(self, other: Expr) -> Option<Self> {
+        Some(match (self, other) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.checked_add(b)?),
+            (Expr::Const(0), other) | (other, Expr::Const(0)) => other,
+            (a, b) => Expr::Add(Rc::new(a), Rc::new(b)),
+        })
+    }
+
+    /// The product of two expressions. Returns `None` if they are constants
+    /// whose product overflows.
+    pub fn checked_mul(self, other: Expr) -> Option<Self> {
+        Some(match (self, other) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.checked_mul(b)?),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), other) | (other, Expr::Const(1)) => other,
+            (a, b) => Expr::Mul(Rc::new(a), Rc::new(b)),
+        })
+    }
+
+    /// Expand the expression into a polynomial in its symbols. Comparisons and
+    /// loads through symbolic addresses have no polynomial form.
+    pub fn to_polynomial(&self) -> Result<Polynomial, SymbolicError> {
+        match self {
+            Expr::Const(value) => Ok(Polynomial::constant(*value)),
+            Expr::Symbol(name) => {
+                let mut monomial = BTreeMap::new();
+                monomial.insert(name.clone(), 1);
+                let mut terms = BTreeMap::new();
+                terms.insert(monomial, 1);
+                Ok(Polynomial { terms })
+            }
+            Expr::Add(a, b) => a.to_polynomial()?.checked_add(&b.to_polynomial()?),
+            Expr::Mul(a, b) => a.to_polynomial()?.checked_mul(&b.to_polynomial()?),
+            Expr::LessThan(..) | Expr::Equals(..) | Expr::Load(_) => {
+                Err(SymbolicError::NotPolynomial)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Symbol(name) => write!(f, "{}", name),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+            Expr::LessThan(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Equals(a, b) => write!(f, "({} == {})", a, b),
+            Expr::Load(address) => write!(f, "[{}]", address),
+        }
+    }
+}
+
+// A product of symbols, each raised to a power
+type Monomial = BTreeMap<Rc<str>, u32>;
+
+/// A sum of integer multiples of products of symbols
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Polynomial {
+    // Terms with a zero coefficient are never stored
+    terms: BTreeMap<Monomial, isize>,
+}
+
+impl Polynomial {
+    /// A polynomial with no symbols
+    fn constant(value: isize) -> Self {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(Monomial::new(), value);
+        }
+        Self { terms }
+    }
+
+    /// Add a term, dropping it if it cancels out
+    fn add_term(&mut self, monomial: Monomial, coefficient: isize) -> Result<(), SymbolicError> {
+        let total = self.terms.entry(monomial.clone()).or_insert(0);
+        *total = total
+            .checked_add(coefficient)
+            .ok_or(SymbolicError::CoefficientOverflow)?;
+        if *total == 0 {
+            self.terms.remove(&monomial);
+        }
+        Ok(())
+    }
+
+    fn checked_add(&self, other: &Polynomial) -> Result<Self, SymbolicError> {
+        let mut sum = self.clone();
+        for (monomial, coefficient) in &other.terms {
+            sum.add_term(monomial.clone(), *coefficient)?;
+        }
+        Ok(sum)
+    }
+
+    fn checked_mul(&self, other: &Polynomial) -> Result<Self, SymbolicError> {
+        let overflow = SymbolicError::CoefficientOverflow;
+        let mut product = Polynomial::constant(0);
+        for (a, a_coefficient) in &self.terms {
+            for (b, b_coefficient) in &other.terms {
+                let mut monomial = a.clone();
+                for (symbol, power) in b {
+                    let total = monomial.entry(symbol.clone()).or_insert(0);
+                    *total = total.checked_add(*power).ok_or_else(|| overflow.clone())?;
+                }
+                let coefficient = a_coefficient
+                    .checked_mul(*b_coefficient)
+                    .ok_or_else(|| overflow.clone())?;
+                product.add_term(monomial, coefficient)?;
+            }
+        }
+        Ok(product)
+    }
+
+    /// The highest total power of any term. Constants have degree zero.
+    pub fn degree(&self) -> u32 {
+        self.terms
+            .keys()
+            .map(|monomial| monomial.values().sum())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The term with no symbols in it
+    pub fn constant_term(&self) -> isize {
+        *self.terms.get(&Monomial::new()).unwrap_or(&0)
+    }
+
+    /// The coefficient of the given symbol on its own, to the first power
+    pub fn coefficient(&self, symbol: &str) -> isize {
+        let mut monomial = Monomial::new();
+        monomial.insert(symbol.into(), 1);
+        *self.terms.get(&monomial).unwrap_or(&0)
+    }
+
+    /// Evaluate the polynomial with the given values for its symbols. Returns
+    /// `None` if a symbol has no value, or the result overflows.
+    pub fn evaluate(&self, values: &HashMap<&str, isize>) -> Option<isize> {
+        let mut total: isize = 0;
+        for (monomial, coefficient) in &self.terms {
+            let mut term = *coefficient;
+            for (symbol, power) in monomial {
+                term = term.checked_mul(values.get(&**symbol)?.checked_pow(*power)?)?;
+            }
+            total = total.checked_add(term)?;
+        }
+        Some(total)
+    }
+}
+
+impl fmt::Display for Polynomial {
+    /// Terms are written highest degree first, and then alphabetically
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+        let mut terms = self.terms.iter().collect::<Vec<_>>();
+        terms.sort_by_key(|(monomial, _)| {
+            let symbols = monomial
+                .iter()
+                .flat_map(|(symbol, power)| (0..*power).map(move |_| symbol.clone()))
+                .collect::<Vec<_>>();
+            (std::cmp::Reverse(symbols.len()), symbols)
+        });
+
+        for (i, (monomial, coefficient)) in terms.into_iter().enumerate() {
+            let sign = match (i, *coefficient < 0) {
+                (0, true) => "-",
+                (0, false) => "",
+                (_, true) => " - ",
+                (_, false) => " + ",
+            };
+            let factors = monomial
+                .iter()
+                .map(|(symbol, power)| match power {
+                    1 => symbol.to_string(),
+                    _ => format!("{}^{}", symbol, power),
+                })
+                .collect::<Vec<_>>()
+                .join("*");
+            let magnitude = coefficient.abs();
+            match (factors.is_empty(), magnitude) {
+                (true, _) => write!(f, "{}{}", sign, magnitude)?,
+                (false, 1) => write!(f, "{}{}", sign, factors)?,
+                (false, _) => write!(f, "{}{}*{}", sign, magnitude, factors)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why symbolic execution could not continue
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum SymbolicError {
+    /// The program failed just as it would have when run concretely
+    Intcode(IntcodeError),
+    /// The instruction itself depends on a symbol
+    SymbolicOpcode { pointer: usize },
+    /// Which instruction runs next, or the relative base, depends on a symbol
+    SymbolicControl { pointer: usize },
+    /// The instruction writes to an address that depends on a symbol
+    SymbolicWrite { pointer: usize },
+    /// Arithmetic on constants overflowed
+    Overflow { pointer: usize },
+    /// The expression has comparisons or loads through symbolic addresses, which
+    /// have no polynomial form
+    NotPolynomial,
+    /// A coefficient or power of the polynomial overflowed
+    CoefficientOverflow,
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Intcode(e) => write!(f, "{}", e),
+            SymbolicError::SymbolicOpcode { pointer } => {
+                write!(f, "Symbolic instruction at {}", pointer)
+            }
+            SymbolicError::SymbolicControl { pointer } => {
+                write!(f, "Control flow depends on a symbol at {}", pointer)
+            }
+            SymbolicError::SymbolicWrite { pointer } => {
+                write!(f, "Write to a symbolic address at {}", pointer)
+            }
+            SymbolicError::Overflow { pointer } => write!(f, "Overflow at {}", pointer),
+            SymbolicError::NotPolynomial => write!(f, "Expression is not a polynomial"),
+            SymbolicError::CoefficientOverflow => write!(f, "Polynomial coefficient overflow"),
+        }
+    }
+}
+
+impl Error for SymbolicError {}
+
+impl From<IntcodeError> for SymbolicError {
+    fn from(e: IntcodeError) -> Self {
+        SymbolicError::Intcode(e)
+    }
+}
+
+/// An Intcode machine where memory cells and inputs may hold symbolic
+/// expressions rather than numbers. Cells that only ever hold numbers behave
+/// exactly as they would in the concrete machine.
+#[derive(Debug, Clone)]
+pub struct Symbolic {
+    memory: Memory,
+    // Cells whose value is not a constant, overriding `memory`
+    symbolic: HashMap<usize, Expr>,
+    pointer: usize,
+    relative_base: isize,
+    input: VecDeque<Expr>,
+    output: Vec<Expr>,
+}
+
+impl Symbolic {
+    /// Replace the value of a memory cell with a named symbol
+    pub fn set_symbol(&mut self, address: usize, name: &str) {
+        self.write(address, Expr::symbol(name));
+    }
+
+    /// Add a value, symbolic or not, to the end of the input queue
+    pub fn push_input(&mut self, value: Expr) {
+        self.input.push_back(value);
+    }
+
+    /// The value of a memory cell
+    pub fn read(&self, address: usize) -> Expr {
+        match self.symbolic.get(&address) {
+            Some(expr) => expr.clone(),
+            None => Expr::Const(self.memory.read(address)),
+        }
+    }
+
+    /// Everything output so far
+    pub fn output(&self) -> &[Expr] {
+        &self.output
+    }
+
+    fn write(&mut self, address: usize, value: Expr) {
+        match value {
+            Expr::Const(value) => {
+                self.symbolic.remove(&address);
+                self.memory.write(address, value);
+            }
+            value => {
+                self.symbolic.insert(address, value);
+            }
+        }
+    }
+
+    /// The value at an address, which may itself be symbolic
+    fn load(&self, address: Expr) -> Result<Expr, SymbolicError> {
+        match address {
+            Expr::Const(address) => Ok(self.read(self.check_address(address)?)),
+            address => Ok(Expr::Load(Rc::new(address))),
+        }
+    }
+
+    fn check_address(&self, address: isize) -> Result<usize, SymbolicError> {
+        if address < 0 {
+            return Err(IntcodeError::NegativeAddress {
+                pointer: self.pointer,
+                address,
+            }
+            .into());
+        }
+        Ok(address as usize)
+    }
+
+    /// Execute until the program halts
+    pub fn execute(&mut self) -> Result<(), SymbolicError> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Execute one instruction. Returns false if the program has halted.
+    fn step(&mut self) -> Result<bool, SymbolicError> {
+        let pointer = self.pointer;
+        if self.symbolic.contains_key(&pointer) {
+            return Err(SymbolicError::SymbolicOpcode { pointer });
+        }
+        let instruction = Instruction::decode(&self.memory, pointer)?;
+        let opcode = instruction.opcode();
+        if opcode == 99 {
+            return Ok(false);
+        }
+
+        // Work out the address each parameter refers to. The parameters are read
+        // again since they might be symbolic.
+        let mut addresses = Vec::new();
+        for (i, (mode, _)) in instruction.parameters().enumerate() {
+            let location = pointer + i + 1;
+            let address = match mode {
+                Mode::Position => self.read(location),
+                Mode::Immediate => Expr::Const(location as isize),
+                Mode::Relative => Expr::Const(self.relative_base)
+                    .checked_add(self.read(location))
+                    .ok_or(SymbolicError::Overflow { pointer })?,
+            };
+            addresses.push(address);
+        }
+        let value = |machine: &Self, i: usize| machine.load(addresses[i].clone());
+        let overflow = SymbolicError::Overflow { pointer };
+
+        let mut next = pointer + instruction.length();
+        let result = match opcode {
+            1 => Some(
+                value(self, 0)?
+                    .checked_add(value(self, 1)?)
+                    .ok_or(overflow)?,
+            ),
+            2 => Some(
+                value(self, 0)?
+                    .checked_mul(value(self, 1)?)
+                    .ok_or(overflow)?,
+            ),
+            3 => Some(
+                self.input
+                    .pop_front()
+                    .ok_or(IntcodeError::InputExhausted { pointer })?,
+            ),
+            4 => {
+                let output = value(self, 0)?;
+                self.output.push(output);
+                None
+            }
+            5 | 6 => {
+                let condition = value(self, 0)?
+                    .as_const()
+                    .ok_or(SymbolicError::SymbolicControl { pointer })?;
+                let target = value(self, 1)?
+                    .as_const()
+                    .ok_or(SymbolicError::SymbolicControl { pointer })?;
+                if (condition != 0) == (opcode == 5) {
+                    next = self.check_address(target)?;
+                }
+                None
+            }
+            7 => Some(Expr::less_than(value(self, 0)?, value(self, 1)?)),
+            8 => Some(Expr::equals(value(self, 0)?, value(self, 1)?)),
+            _ => {
+                let adjustment = value(self, 0)?
+                    .as_const()
+                    .ok_or(SymbolicError::SymbolicControl { pointer })?;
+                self.relative_base = self.relative_base.checked_add(adjustment).ok_or(overflow)?;
+                None
+            }
+        };
+
+        if let Some((operand, result)) = write_operand(opcode).zip(result) {
+            let address = addresses[operand]
+                .as_const()
+                .ok_or(SymbolicError::SymbolicWrite { pointer })?;
+            let address = self.check_address(address)?;
+            self.write(address, result);
+        }
+        self.pointer = next;
+        Ok(true)
+    }
+}
+
+impl Intcode {
+    /// A symbolic machine in the same state as this one, ready to have some of its
+    /// memory replaced with symbols
+    pub fn symbolic(&self) -> Symbolic {
+        Symbolic {
+            memory: self.memory.clone(),
+            symbolic: HashMap::new(),
+            pointer: self.pointer,
+            relative_base: self.relative_base,
+            input: self.input.iter().map(|value| Expr::Const(*value)).collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_polynomial() {
+        // ADD [9], [10], 3 / MUL [3], [11], 0 / HLT, with cell 9 made symbolic
+        let mut machine = Intcode::new("1,9,10,3,2,3,11,0,99,30,40,50")
+            .unwrap()
+            .symbolic();
+        machine.set_symbol(9, "x");
+        machine.execute().unwrap();
+
+        assert_eq!(machine.read(0).to_string(), "((x + 40) * 50)");
+        let polynomial = machine.read(0).to_polynomial().unwrap();
+        assert_eq!(polynomial.to_string(), "50*x + 2000");
+        assert_eq!(polynomial.degree(), 1);
+        assert_eq!(polynomial.coefficient("x"), 50);
+        assert_eq!(polynomial.constant_term(), 2000);
+
+        let mut values = HashMap::new();
+        values.insert("x", 30);
+        assert_eq!(polynomial.evaluate(&values), Some(3500));
+    }
+
+    #[test]
+    fn concrete_execution() {
+        let mut symbolic = Intcode::new("3,9,1002,9,2,9,4,9,99,0").unwrap().symbolic();
+        symbolic.push_input(Expr::Const(21));
+        symbolic.execute().unwrap();
+        assert_eq!(symbolic.output(), &[Expr::Const(42)]);
+
+        // With a symbolic input instead
+        let mut symbolic = Intcode::new("3,9,1002,9,2,9,4,9,99,0").unwrap().symbolic();
+        symbolic.push_input(Expr::symbol("n"));
+        symbolic.execute().unwrap();
+        let output = symbolic.output()[0].to_polynomial().unwrap();
+        assert_eq!(output.to_string(), "2*n");
+    }
+
+    #[test]
+    fn symbolic_addresses() {
+        // ADD [5], [5], 0 with cell 1 made symbolic reads through a symbolic address
+        let mut machine = Intcode::new("1,5,5,0,99,7").unwrap().symbolic();
+        machine.set_symbol(1, "p");
+        machine.execute().unwrap();
+        assert_eq!(machine.read(0).to_string(), "([p] + 7)");
+        assert_eq!(
+            machine.read(0).to_polynomial(),
+            Err(SymbolicError::NotPolynomial)
+        );
+
+        // But writing through one is an error
+        let mut machine = Intcode::new("1,5,5,0,99,7").unwrap().symbolic();
+        machine.set_symbol(3, "p");
+        assert_eq!(
+            machine.execute(),
+            Err(SymbolicError::SymbolicWrite { pointer: 0 })
+        );
+    }
+
+    #[test]
+    fn symbolic_branch() {
+        // IN c / JNZ c, #5 / HLT
+        let mut machine = Intcode::new("3,6,1005,6,5,99,0").unwrap().symbolic();
+        machine.push_input(Expr::symbol("c"));
+        assert_eq!(
+            machine.execute(),
+            Err(SymbolicError::SymbolicControl { pointer: 2 })
+        );
+    }
+
+    #[test]
+    fn polynomial_arithmetic() {
+        let x = Expr::symbol("x");
+        let y = Expr::symbol("y");
+        let minus = |e: Expr| Expr::Const(-1).checked_mul(e).unwrap();
+        let difference = x.clone().checked_add(minus(y)).unwrap();
+        let expr = difference.clone().checked_mul(difference).unwrap();
+        let polynomial = expr.to_polynomial().unwrap();
+        assert_eq!(polynomial.to_string(), "x^2 - 2*x*y + y^2");
+        assert_eq!(polynomial.degree(), 2);
+
+        let cancelled = x.clone().checked_add(minus(x)).unwrap();
+        assert_eq!(cancelled.to_polynomial().unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn overflow() {
+        // MUL #max, #2, 0 / HLT
+        let mut machine = Intcode::new(&format!("1102,{},2,0,99", isize::MAX))
+            .unwrap()
+            .symbolic();
+        assert_eq!(
+            machine.execute(),
+            Err(SymbolicError::Overflow { pointer: 0 })
+        );
+
+        let big = Expr::Const(isize::MAX)
+            .checked_mul(Expr::symbol("x"))
+            .unwrap();
+        let doubled = big.clone().checked_add(big).unwrap();
+        assert_eq!(
+            doubled.to_polynomial(),
+            Err(SymbolicError::CoefficientOverflow)
+        );
+    }
+}
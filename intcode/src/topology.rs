@@ -0,0 +1,241 @@
+use crate::{Intcode, IntcodeError};
+
+/// A machine that cannot make progress because it is waiting for input
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Blocked {
+    /// The index of the blocked machine
+    pub machine: usize,
+    /// The address of the input instruction it is stuck on
+    pub pointer: usize,
+    /// The machines whose output feeds this machine's input
+    pub waiting_on: Vec<usize>,
+}
+
+/// How a run of a topology ended
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Outcome {
+    /// Every machine halted
+    Halted,
+    /// No machine can make progress. Lists every machine that is waiting for input.
+    Deadlock(Vec<Blocked>),
+}
+
+/// A set of Intcode machines with each machine's output wired to the input of others.
+/// A machine with several outgoing edges sends every output value to each of them.
+/// A machine with no outgoing edges keeps its output on its own output tape.
+#[derive(Default)]
+pub struct Topology {
+    machines: Vec<Intcode>,
+    edges: Vec<(usize, usize)>,
+    last_outputs: Vec<Option<isize>>,
+}
+
+impl Topology {
+    /// Create an empty topology
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect the given machines in a line, each one's output feeding the next one's input
+    pub fn chain(machines: Vec<Intcode>) -> Self {
+        let mut topology = Self::new();
+        for machine in machines {
+            topology.add_machine(machine);
+        }
+        for i in 1..topology.machines.len() {
+            topology.connect(i - 1, i);
+        }
+        topology
+    }
+
+    /// Connect the given machines in a chain, with the last one's output feeding back
+    /// into the first one's input
+    pub fn ring(machines: Vec<Intcode>) -> Self {
+        let mut topology = Self::chain(machines);
+        if !topology.machines.is_empty() {
+            topology.connect(topology.machines.len() - 1, 0);
+        }
+        topology
+    }
+
+    /// Add a machine, returning its index
+    pub fn add_machine(&mut self, machine: Intcode) -> usize {
+        self.machines.push(machine);
+        self.last_outputs.push(None);
+        self.machines.len() - 1
+    }
+
+    /// Send everything the `from` machine outputs to the `to` machine's input
+    pub fn connect(&mut self, from: usize, to: usize) -> &mut Self {
+        assert!(from < self.machines.len(), "No machine {}", from);
+        assert!(to < self.machines.len(), "No machine {}", to);
+        self.edges.push((from, to));
+        self
+    }
+
+    /// The machine at the given index
+    pub fn machine(&self, index: usize) -> &Intcode {
+        &self.machines[index]
+    }
+
+    /// Mutable access to the machine at the given index, for example to push input
+    pub fn machine_mut(&mut self, index: usize) -> &mut Intcode {
+        &mut self.machines[index]
+    }
+
+    /// The most recent value output by the machine at the given index, whether it
+    /// was kept or sent to other machines
+    pub fn last_output(&self, index: usize) -> Option<isize> {
+        self.last_outputs[index]
+    }
+
+    /// Run every machine, passing output along the edges, until they all halt or
+    /// none of them can make any more progress
+    pub fn run(&mut self) -> Result<Outcome, IntcodeError> {
+        loop {
+            let mut progress = false;
+
+            for i in 0..self.machines.len() {
+                let steps = self.machines[i].steps();
+                self.machines[i].execute()?;
+                if self.machines[i].steps() != steps {
+                    progress = true;
+                }
+
+                let targets: Vec<usize> = self
+                    .edges
+                    .iter()
+                    .filter(|(from, _)| *from == i)
+                    .map(|(_, to)| *to)
+                    .collect();
+
+                if let Some(value) = self.machines[i].output().back() {
+                    self.last_outputs[i] = Some(*value);
+                }
+                if targets.is_empty() {
+                    continue;
+                }
+                for value in self.machines[i].take_output() {
+                    for target in &targets {
+                        self.machines[*target].push_input(value);
+                    }
+                }
+            }
+
+            if self.machines.iter().all(Intcode::is_halted) {
+                return Ok(Outcome::Halted);
+            }
+            if !progress {
+                return Ok(Outcome::Deadlock(self.blocked()));
+            }
+        }
+    }
+
+    /// Every machine that is waiting for input
+    fn blocked(&self) -> Vec<Blocked> {
+        self.machines
+            .iter()
+            .enumerate()
+            .filter(|(_, machine)| !machine.is_halted())
+            .map(|(i, machine)| Blocked {
+                machine: i,
+                pointer: machine.pointer(),
+                waiting_on: self
+                    .edges
+                    .iter()
+                    .filter(|(_, to)| *to == i)
+                    .map(|(from, _)| *from)
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Output double each input, halting on an input of zero. See the io tests.
+    const DOUBLER: &str = "3,15,1006,15,14,1002,15,2,15,4,15,1106,0,0,99,0";
+
+    // Read two inputs and output their sum
+    const ADDER: &str = "3,11,3,12,1,11,12,11,4,11,99,0,0";
+
+    // Read one input and output it doubled
+    const DOUBLE_ONCE: &str = "3,9,1002,9,2,9,4,9,99,0";
+
+    fn machine(program: &str) -> Intcode {
+        Intcode::new(program).unwrap()
+    }
+
+    #[test]
+    fn chain() {
+        let mut topology =
+            Topology::chain(vec![machine(DOUBLER), machine(DOUBLER), machine(DOUBLER)]);
+        topology.machine_mut(0).push_input(1);
+        topology.machine_mut(0).push_input(5);
+
+        // The zero never makes it down the chain, so the later machines are left waiting
+        topology.machine_mut(0).push_input(0);
+        let outcome = topology.run().unwrap();
+        assert_eq!(
+            outcome,
+            Outcome::Deadlock(vec![
+                Blocked {
+                    machine: 1,
+                    pointer: 0,
+                    waiting_on: vec![0]
+                },
+                Blocked {
+                    machine: 2,
+                    pointer: 0,
+                    waiting_on: vec![1]
+                },
+            ])
+        );
+        assert_eq!(topology.machine(2).get_output(), vec![8, 40]);
+        assert_eq!(topology.last_output(0), Some(10));
+    }
+
+    #[test]
+    fn ring_deadlock() {
+        let mut topology = Topology::ring(vec![machine(DOUBLER), machine(DOUBLER)]);
+        let outcome = topology.run().unwrap();
+        assert_eq!(
+            outcome,
+            Outcome::Deadlock(vec![
+                Blocked {
+                    machine: 0,
+                    pointer: 0,
+                    waiting_on: vec![1]
+                },
+                Blocked {
+                    machine: 1,
+                    pointer: 0,
+                    waiting_on: vec![0]
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn graph() {
+        // Two sources fan in to an adder, which fans out to two sinks
+        let mut topology = Topology::new();
+        let left = topology.add_machine(machine("104,3,99"));
+        let right = topology.add_machine(machine("104,4,99"));
+        let adder = topology.add_machine(machine(ADDER));
+        let first = topology.add_machine(machine(DOUBLE_ONCE));
+        let second = topology.add_machine(machine(ADDER));
+        topology
+            .connect(left, adder)
+            .connect(right, adder)
+            .connect(adder, first)
+            .connect(adder, second);
+        topology.machine_mut(second).push_input(10);
+
+        assert_eq!(topology.run().unwrap(), Outcome::Halted);
+        assert_eq!(topology.machine(first).get_output(), vec![14]);
+        assert_eq!(topology.machine(second).get_output(), vec![17]);
+    }
+}
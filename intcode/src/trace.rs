@@ -0,0 +1,236 @@
+use crate::instruction::write_operand;
+use crate::{Intcode, Operation, Word};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A record of a single executed instruction
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct TraceEntry<W = isize> {
+    /// How many instructions the machine had executed before this one
+    pub step: u64,
+    /// The address of the instruction
+    pub pointer: usize,
+    /// The opcode along with its parameter modes, exactly as it appeared on the tape
+    pub raw_opcode: W,
+    /// The resolved address of each operand, and the value there before execution
+    pub operands: Vec<(usize, W)>,
+    /// The address and new value of the memory cell written, if any
+    pub write: Option<(usize, W)>,
+    /// The value consumed from the input, if any
+    pub input: Option<W>,
+    /// The value produced on the output, if any
+    pub output: Option<W>,
+}
+
+impl<W: Word> TraceEntry<W> {
+    /// Start an entry for an operation that is about to be executed
+    pub(crate) fn before(machine: &Intcode<W>, operation: &Operation) -> Self {
+        Self {
+            step: machine.steps,
+            pointer: machine.pointer,
+            raw_opcode: machine.memory.read(machine.pointer),
+            operands: operation
+                .operand_locations()
+                .iter()
+                .map(|location| (*location, machine.memory.read(*location)))
+                .collect(),
+            write: None,
+            input: None,
+            output: None,
+        }
+    }
+
+    /// Fill in the effects of an operation that was just executed
+    pub(crate) fn after(
+        mut self,
+        machine: &Intcode<W>,
+        operation: &Operation,
+        output: Option<W>,
+    ) -> Self {
+        self.write = write_operand(operation.opcode).map(|i| {
+            let location = operation.operand_locations[i];
+            (location, machine.memory.read(location))
+        });
+        if operation.opcode == 3 {
+            self.input = self.write.as_ref().map(|(_, value)| value.clone());
+        }
+        self.output = output;
+        self
+    }
+
+    /// Render the entry as a single line of JSON. Fields for effects that did not
+    /// happen are left out.
+    pub fn to_json(&self) -> String {
+        let operands = self
+            .operands
+            .iter()
+            .map(|(address, value)| format!("{{\"address\":{},\"value\":{}}}", address, value))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut json = format!(
+            "{{\"step\":{},\"pointer\":{},\"opcode\":{},\"operands\":[{}]",
+            self.step, self.pointer, self.raw_opcode, operands
+        );
+        if let Some((address, value)) = &self.write {
+            json.push_str(&format!(
+                ",\"write\":{{\"address\":{},\"value\":{}}}",
+                address, value
+            ));
+        }
+        if let Some(value) = &self.input {
+            json.push_str(&format!(",\"input\":{}", value));
+        }
+        if let Some(value) = &self.output {
+            json.push_str(&format!(",\"output\":{}", value));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Every instruction executed by a machine since tracing was enabled
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Trace<W = isize> {
+    entries: Vec<TraceEntry<W>>,
+}
+
+impl<W> Default for Trace<W> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<W: Word> Trace<W> {
+    /// The recorded entries, in execution order
+    pub fn entries(&self) -> &[TraceEntry<W>] {
+        &self.entries
+    }
+
+    /// Add an entry to the end of the trace
+    pub(crate) fn push(&mut self, entry: TraceEntry<W>) {
+        self.entries.push(entry);
+    }
+
+    /// Remove the entry for the given step, if it is the last one recorded
+    pub(crate) fn unrecord(&mut self, step: u64) {
+        if self.entries.last().map(|entry| entry.step) == Some(step) {
+            self.entries.pop();
+        }
+    }
+
+    /// Find the index of the first entry that differs between two traces, or where one
+    /// trace ends before the other. Returns `None` if the traces are identical.
+    pub fn divergence(&self, other: &Trace<W>) -> Option<usize> {
+        let common = self
+            .entries
+            .iter()
+            .zip(other.entries.iter())
+            .position(|(a, b)| a != b);
+
+        match common {
+            Some(index) => Some(index),
+            None if self.entries.len() != other.entries.len() => {
+                Some(self.entries.len().min(other.entries.len()))
+            }
+            None => None,
+        }
+    }
+
+    /// Write the trace as JSON Lines, one entry per line
+    pub fn write_jsonl<O: Write>(&self, mut writer: O) -> io::Result<()> {
+        for entry in &self.entries {
+            writeln!(writer, "{}", entry.to_json())?;
+        }
+        Ok(())
+    }
+
+    /// Write the trace to the given file as JSON Lines
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_jsonl(&mut writer)?;
+        writer.flush()
+    }
+}
+
+impl<W: Word> Intcode<W> {
+    /// Start recording every instruction this machine executes. Any previous
+    /// trace is discarded.
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    /// The trace recorded so far, if tracing is enabled
+    pub fn trace(&self) -> Option<&Trace<W>> {
+        self.trace.as_ref()
+    }
+
+    /// Stop tracing, returning the trace recorded so far
+    pub fn take_trace(&mut self) -> Option<Trace<W>> {
+        self.trace.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_effects() {
+        let mut machine = Intcode::new("3,9,1002,9,2,9,4,9,99,0").unwrap();
+        machine.enable_trace();
+        machine.push_input(21);
+        machine.execute().unwrap();
+
+        let entries = machine.trace().unwrap().entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            TraceEntry {
+                step: 0,
+                pointer: 0,
+                raw_opcode: 3,
+                operands: vec![(9, 0)],
+                write: Some((9, 21)),
+                input: Some(21),
+                output: None,
+            }
+        );
+        assert_eq!(
+            entries[1].to_json(),
+            r#"{"step":1,"pointer":2,"opcode":1002,"operands":[{"address":9,"value":21},{"address":4,"value":2},{"address":9,"value":21}],"write":{"address":9,"value":42}}"#
+        );
+        assert_eq!(
+            entries[2].to_json(),
+            r#"{"step":2,"pointer":6,"opcode":4,"operands":[{"address":9,"value":42}],"output":42}"#
+        );
+    }
+
+    #[test]
+    fn divergence() {
+        let run = |program: &str| {
+            let mut machine = Intcode::new(program).unwrap();
+            machine.enable_trace();
+            machine.execute().unwrap();
+            machine.take_trace().unwrap()
+        };
+
+        let original = run("1101,1,2,9,104,7,4,9,99,0");
+        let changed = run("1101,1,3,9,104,7,4,9,99,0");
+        assert_eq!(original.divergence(&original), None);
+        assert_eq!(original.divergence(&changed), Some(0));
+
+        let shorter = run("1101,1,2,9,104,7,99,0,0,0");
+        assert_eq!(original.divergence(&shorter), Some(2));
+    }
+
+    #[test]
+    fn tracing_is_opt_in() {
+        let mut machine = Intcode::new("104,1,99").unwrap();
+        machine.execute().unwrap();
+        assert!(machine.trace().is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::memory::Memory;
+use crate::{Intcode, IntcodeError, Operation, Word};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Everything that decides what a machine does next. Output is left out, since
+/// a machine never reads back what it has written out.
+#[derive(Eq, PartialEq, Hash, Debug, Clone)]
+struct State<W: Word> {
+    pointer: usize,
+    relative_base: isize,
+    memory: Memory<W>,
+    input: VecDeque<W>,
+}
+
+/// The states a machine was in at each backward jump it took, with the step each
+/// was first seen at. States are bucketed by hash and compared in full, so a
+/// hash collision can't be mistaken for a loop.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub(crate) struct LoopDetector<W: Word> {
+    seen: HashMap<u64, Vec<(State<W>, u64)>>,
+}
+
+impl<W: Word> Default for LoopDetector<W> {
+    fn default() -> Self {
+        Self {
+            seen: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Word> LoopDetector<W> {
+    /// Remember a state, returning the step it was first seen at if it has been
+    /// seen before
+    fn visit(&mut self, state: State<W>, step: u64) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        state.hash(&mut hasher);
+        let bucket = self.seen.entry(hasher.finish()).or_default();
+        if let Some((_, first)) = bucket.iter().find(|(seen, _)| *seen == state) {
+            return Some(*first);
+        }
+        bucket.push((state, step));
+        None
+    }
+}
+
+impl<W: Word> Intcode<W> {
+    /// Stop with `IntcodeError::StepLimit` instead of executing more than `limit`
+    /// instructions in total. `None` removes the limit.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    /// Turn loop detection on or off. While it is on, the machine remembers its
+    /// whole state at every backward jump, and stops with
+    /// `IntcodeError::InfiniteLoop` if it comes back to a state it has been in
+    /// before, since it would then go round forever. The check is exact, but
+    /// copies memory on every backward jump, so long running loops are better
+    /// caught with a step limit.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loops = if enabled {
+            Some(LoopDetector::default())
+        } else {
+            None
+        };
+    }
+
+    /// Fail if executing another instruction would go over the step limit
+    pub(crate) fn check_step_limit(&self) -> Result<(), IntcodeError> {
+        match self.step_limit {
+            Some(limit) if self.steps >= limit => Err(IntcodeError::StepLimit {
+                pointer: self.pointer,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if the operation just executed from `from` jumped back to a state
+    /// the machine has already been in
+    pub(crate) fn check_loop(
+        &mut self,
+        from: usize,
+        operation: &Operation,
+    ) -> Result<(), IntcodeError> {
+        let backward = (operation.opcode == 5 || operation.opcode == 6) && self.pointer <= from;
+        let loops = match self.loops.as_mut() {
+            Some(loops) if backward => loops,
+            _ => return Ok(()),
+        };
+
+        let state = State {
+            pointer: self.pointer,
+            relative_base: self.relative_base,
+            memory: self.memory.clone(),
+            input: self.input.clone(),
+        };
+        match loops.visit(state, self.steps) {
+            Some(first) => Err(IntcodeError::InfiniteLoop {
+                start: self.pointer,
+                length: self.steps - first,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Forget the states seen so far. Called whenever the machine is changed from
+    /// outside, since a repeated state no longer means it went round on its own.
+    pub(crate) fn forget_states(&mut self) {
+        if let Some(loops) = self.loops.as_mut() {
+            loops.seen.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RunState;
+
+    #[test]
+    fn step_limit() {
+        // loop: JNZ #1, #loop
+        let mut machine = Intcode::new("1105,1,0").unwrap();
+        machine.set_step_limit(Some(100));
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::StepLimit {
+                pointer: 0,
+                limit: 100
+            })
+        );
+        assert_eq!(machine.steps(), 100);
+    }
+
+    #[test]
+    fn detects_loops() {
+        // loop: EQ x, #0, x / JNZ #1, #loop / x: data 0
+        let mut machine = Intcode::new("1008,7,0,7,1105,1,0,0").unwrap();
+        machine.set_loop_detection(true);
+        assert_eq!(
+            machine.execute(),
+            Err(IntcodeError::InfiniteLoop {
+                start: 0,
+                length: 4
+            })
+        );
+    }
+
+    #[test]
+    fn terminating_loops_pass() {
+        // IN c / loop: ADD c, #-1, c / JNZ c, #loop / OUT c / HLT / c: data 0
+        let mut machine = Intcode::new("3,12,1001,12,-1,12,1005,12,2,4,12,99,0").unwrap();
+        machine.set_loop_detection(true);
+        machine.push_input(100);
+        machine.execute().unwrap();
+        assert_eq!(machine.get_output(), vec![0]);
+    }
+
+    #[test]
+    fn input_resets_detection() {
+        // loop: IN x / JNZ #1, #loop / x: data 0
+        let mut machine = Intcode::new("3,5,1105,1,0,0").unwrap();
+        machine.set_loop_detection(true);
+        for _ in 0..3 {
+            machine.push_input(7);
+            assert_eq!(machine.execute(), Ok(RunState::NeedsInput));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// A type that can hold the value of an Intcode memory cell. Addresses, opcodes
+/// and the relative base are always machine sized, but the values on the tape
+/// can be as wide as the program needs.
+pub trait Word: Clone + Eq + Ord + Hash + Debug + Display + FromStr {
+    /// Convert from a machine sized integer
+    fn from_isize(value: isize) -> Self;
+
+    /// Convert to a machine sized integer, if it fits
+    fn to_isize(&self) -> Option<isize>;
+
+    /// Add, wrapping around on overflow
+    fn wrapping_add(&self, other: &Self) -> Self;
+
+    /// Multiply, wrapping around on overflow
+    fn wrapping_mul(&self, other: &Self) -> Self;
+
+    /// Add, or `None` on overflow
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+
+    /// Multiply, or `None` on overflow
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+}
+
+macro_rules! primitive_word {
+    ($($t:ty),*) => {$(
+        impl Word for $t {
+            fn from_isize(value: isize) -> Self {
+                value as $t
+            }
+
+            fn to_isize(&self) -> Option<isize> {
+                use std::convert::TryFrom;
+                isize::try_from(*self).ok()
+            }
+
+            fn wrapping_add(&self, other: &Self) -> Self {
+                <$t>::wrapping_add(*self, *other)
+            }
+
+            fn wrapping_mul(&self, other: &Self) -> Self {
+                <$t>::wrapping_mul(*self, *other)
+            }
+
+            fn checked_add(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_add(*self, *other)
+            }
+
+            fn checked_mul(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_mul(*self, *other)
+            }
+        }
+    )*};
+}
+
+primitive_word!(isize, i64, i128);
+
+// Big integers never overflow, so wrapping and checked arithmetic are the same
+#[cfg(feature = "bigint")]
+impl Word for num_bigint::BigInt {
+    fn from_isize(value: isize) -> Self {
+        value.into()
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        num_traits::ToPrimitive::to_isize(self)
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+}